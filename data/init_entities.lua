@@ -1,9 +1,10 @@
 o = { {
 	Hitbox = {
-		size = { 0.2, 1.0 },
-		pos = { 0.3, 0.8 },
-		simulate = true,
-		mass = 1,
+		size = { 0.2, 0.1 },
+		pos = { 0.3, 0.2 },
+		simulate = true, -- falls, gets pushed by sand and other boxes, false pins it in place
+		mass = 1, -- scales gravity and how hard grains push it
+		shape = "box", -- or "circle" with radius, or "rotated" with angle in radians, always centred in pos to pos + size
 	},
 	Sprite = {
 		size = {0.25, 0.25},
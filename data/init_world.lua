@@ -2,8 +2,8 @@ local math = require("math")
 
 function gen_p(index)
 	return {
-		colour = {math.random(), math.random(), math.random()},
-		id = index,
+		material = index % 16 == 0 and "acid" or index % 4 == 0 and "water" or "sand",
+		colour = index % 16 ~= 0 and {math.random(), math.random(), math.random()} or nil, -- keep acid green
 		pos = { (index / 64 % 0.978241), (index / 50 % 0.832) }
 	}
 end
@@ -14,4 +14,30 @@ for i = 1,64*8 do
 	table.insert(out,gen_p(i))
 end
 
+-- a bridge of static particles, sand piling on it will eventually snap it
+for i = 0, 63 do
+	table.insert(out, {
+		material = "stone", -- stable comes from the material
+		pos = { 0.2 + i * 0.0095, 0.75 },
+		force = 1.0, -- pull back to target, target defaults to pos
+	})
+end
+
+-- smoke rises, bubbles only rise while they are surrounded by something heavier
+for i = 0, 63 do
+	table.insert(out, {
+		material = i % 2 == 0 and "smoke" or "bubble",
+		pos = { 0.3 + (i % 8) * 0.05, 0.9 + math.floor(i / 8) * 0.01 },
+		-- gas can be set per particle too, "normal", "antigravity", "floating" or "buoyant", numbers work too
+	})
+end
+
+-- lava poured in from the top left, it sets into stone and boils any water it lands in
+for i = 0, 63 do
+	table.insert(out, {
+		material = "lava",
+		pos = { 0.05 + (i % 8) * 0.012, 0.05 + math.floor(i / 8) * 0.012 },
+	})
+end
+
 return out
\ No newline at end of file
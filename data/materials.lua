@@ -0,0 +1,106 @@
+--[[
+	Material registry
+	Particles in init_world.lua can just say material = "sand" and get everything here,
+	anything they set themselves still wins. ids index the gpu side table so keep them small.
+	temperature is what particles start at (default 20), conductivity is 0-1 (default 0.1).
+	Above melt_point a particle turns into melt_into, below freeze_point into freeze_into.
+]]
+
+return {
+	sand = {
+		id = 1,
+		colour = { 0.9, 0.8, 0.45 },
+		mass = 1.0,
+		friction = 0.05,
+	},
+	stone = {
+		id = 2,
+		colour = { 0.45, 0.45, 0.5 },
+		mass = 2.0,
+		friction = 0.2,
+		stable = 0.02, -- particles with a force use this to decide when to break off
+		tags = { "static", "soluble" }, -- names from tags.lua
+		conductivity = 0.05,
+		melt_point = 1000,
+		melt_into = "lava",
+	},
+	water = {
+		id = 3,
+		colour = { 0.2, 0.4, 0.9 },
+		mass = 0.8,
+		friction = 0.0,
+		tags = { "liquid", "conductive" },
+		conductivity = 0.3,
+		melt_point = 100, -- boiling
+		melt_into = "steam",
+		freeze_point = 0,
+		freeze_into = "ice",
+	},
+	smoke = {
+		id = 4,
+		colour = { 0.8, 0.8, 0.85 },
+		mass = 0.2,
+		gas = "antigravity",
+	},
+	bubble = {
+		id = 5,
+		colour = { 0.7, 0.9, 1.0 },
+		mass = 0.2,
+		gas = "buoyant",
+	},
+	wood = {
+		id = 6,
+		colour = { 0.5, 0.3, 0.15 },
+		mass = 1.5,
+		friction = 0.3,
+		stable = 0.02,
+		tags = { "static", "flammable" },
+		melt_point = 300, -- catches fire when hot enough
+		melt_into = "fire",
+	},
+	fire = {
+		id = 7,
+		colour = { 1.0, 0.45, 0.1 },
+		mass = 0.1,
+		gas = "antigravity",
+		tags = "burning",
+		temperature = 600,
+	},
+	acid = {
+		id = 8,
+		colour = { 0.4, 1.0, 0.2 },
+		mass = 0.9,
+		tags = { "liquid", "corrosive" },
+	},
+	steam = {
+		id = 9,
+		colour = { 0.9, 0.9, 0.95 },
+		mass = 0.1,
+		gas = "antigravity",
+		temperature = 110,
+		conductivity = 0.2,
+		freeze_point = 95, -- condensing
+		freeze_into = "water",
+	},
+	lava = {
+		id = 10,
+		colour = { 1.0, 0.3, 0.05 },
+		mass = 2.5,
+		friction = 0.4,
+		tags = "liquid", -- not burning or it would burn out into smoke, it lights wood by heating it instead
+		temperature = 1200,
+		conductivity = 0.1,
+		freeze_point = 700, -- cools into stone
+		freeze_into = "stone",
+	},
+	ice = {
+		id = 11,
+		colour = { 0.8, 0.9, 1.0 },
+		mass = 0.7,
+		friction = 0.5,
+		temperature = -10,
+		conductivity = 0.3,
+		melt_point = 1,
+		melt_into = "water",
+	},
+}
@@ -0,0 +1,13 @@
+--[[
+	Reactions, checked every substep on the gpu.
+	A particle with any tag in `tag` touching a particle with any tag in `touching` turns into
+	the material `into` with probability `chance`. Leave out touching to react on its own.
+	The new particle keeps its position, velocity and temperature but is no longer pinned to a target.
+]]
+
+return {
+	{ tag = "flammable", touching = "burning", into = "fire", chance = 0.05 }, -- fire spreads
+	{ tag = "burning", into = "smoke", chance = 0.005 },                     -- and burns out
+	{ tag = "soluble", touching = "corrosive", into = "smoke", chance = 0.02 }, -- acid eats through things
+	{ tag = "corrosive", touching = "soluble", into = "smoke", chance = 0.005 }, -- slowly getting used up
+}
@@ -0,0 +1,15 @@
+--[[
+	Tag names, each one gets a bit of Material.tags in the order they are listed.
+	Materials and particles can then use tags = { "flammable", "static" } instead of numbers.
+	At most 32.
+]]
+
+return {
+	"flammable",
+	"burning",
+	"conductive",
+	"corrosive",
+	"soluble",
+	"static",
+	"liquid",
+}
@@ -12,6 +12,7 @@ math = require("math")
 st = st or
 	GetTime() -- im not going to write a vscode language server for these funcs so their syntax wont get highlighted.
 -- print("fps: " .. tostring(GetFrame() / (GetTime() - st) * 1000))
+-- GetDeltaTime() is how many seconds the world moved since the last run of this script
 
 local data = EntityGetComponentValue(0, "sprite.pos")
 EntitySetComponentValue(0, "sprite.pos", { data.x + 0.50, data.y })
@@ -24,23 +25,37 @@ print(data) -- multiple types support
 -- this also creates a strange visual effect depending on monitor due to ghosting taking a frame to clear.
 -- not deleting here because its the only interesting entity
 
+-- local box = GetEntity(0) -- same fields as the paths above, edits queue the same way
+-- box.hitbox.vel.x = box.hitbox.vel.x + 0.01
+-- box.sprite.scale = { 1, 1 }
+-- if box.data == "clean" then box.hitbox.mass = 2 end
+
 -- local e = GetEntities()
 -- for k, v in ipairs(e) do
 -- 	local d = EntityGetComponentValue(v, "data")
 -- 	if d == "clean" then
 -- 		EntitySetComponentValue(v, "hitbox.size", { math.random(), math.random() })
--- 		EntitySetComponentValue(v, "data", {"dirty"})
--- 		for k,v in ipairs(RS_deltas) do
--- 			for k,v in ipairs(v) do
--- 				print(v)
--- 			end
--- 		end
+-- 		EntitySetComponentValue(v, "data", {"dirty"}) -- strings go through untouched, quotes and all
 -- 	end
 -- end
 
+-- SetPhysics({ gravity = -0.0005 }) -- any field from world_settings.lua can be changed while running
+
+-- local grain = SpawnParticle({ material = "sand", pos = { math.random(), 0.9 } }) -- same fields as init_world.lua
+-- DestroyParticle(grain) -- frees the slot for the next spawn, returns false if it was already free
+
+-- for _, hit in ipairs(GetCollisions(0)) do -- everything that touched entity 0 since the last run, nil for all entities
+-- 	print(hit.material or ("entity " .. hit.other), hit.impulse.x, hit.impulse.y) -- material is nil for box on box
+-- end
+-- function OnCollision(hit) -- same tables, called once for each before this script runs
+-- 	if hit.material == "lava" then EntitySetComponentValue(hit.entity, "deleted", { true }) end
+-- end
+-- function OnReload(file) -- saving this file or F5 recompiles it, globals like st above survive
+-- 	print("reloaded " .. file)
+-- end
+
 if math.random() <= 0.05 and GetFrame() >= 3 then
-	CreateEntity() -- we can use this in 1 frame
-	-- we can crash the app if we allocate too many, in a real app you would responsibly delete old entities.
+	CreateEntity() -- returns the new id or nil when full, we can use this in 1 frame
+	-- once every slot is taken this does nothing, in a real app you would responsibly delete old entities.
 end
 
--- RS_deltas = {{0,"data","fish"}} RS_deltas can be manually handled in here, if you are a bit crazy
@@ -0,0 +1,21 @@
+--[[
+	World physics, loaded once at startup.
+	Scripts can change any of these while running with SetPhysics({ gravity = -0.0005 }).
+]]
+
+return {
+	gravity = 0.0005,    -- added to vel.y every substep, positive is down
+	radius = 0.02,       -- particles closer than this push apart, at most one grid cell (0.02)
+	max_speed = 0.1,
+	damping = 0.999,     -- vel is multiplied by this every substep
+	jitter = 0.0001,     -- random nudges, stops perfectly stacked columns
+	wall_margin = 0.005, -- how close to the edge before the walls push back
+	wall_margin_left = 0.0005, -- the low x wall lets grains ten times closer
+	wall_push = 0.0006,
+	dt = 1 / 300,        -- seconds per substep, everything above is tuned for 1 / 300
+	size = { 1, 1 },     -- the world runs from 0 to size, the window shows all of it
+	boundary = "wall",   -- "wall", "wrap" or "open" (particles leaving are destroyed), boundary_x and boundary_y set one axis
+	restitution = 0,     -- fraction of speed kept bouncing off a wall
+	max_catch_up = 10,   -- most ticks (two substeps each) one frame may run after a hitch
+	-- seed = 1234,      -- fixes math.random and the shader's random numbers, --seed overrides it, unset picks one from the clock
+}
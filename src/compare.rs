@@ -0,0 +1,225 @@
+use std::sync::Arc;
+
+use glam::Vec2;
+use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
+use vulkano::memory::allocator::StandardMemoryAllocator;
+use vulkano::padded::Padded;
+use vulkano::sync::GpuFuture;
+
+use crate::deploy_shader;
+use crate::gpu_constructor;
+use crate::simulation::materials::MaterialRegistry;
+use crate::simulation::sand::sand_shader::{Clock, Hitbox, Material, WorldSettings};
+use crate::simulation::sand::{
+    self, cpu, upload_standard_buffer, ParticlePool, HITBOX_PADDING, PADDING,
+};
+use crate::simulation::terrain::Terrain;
+
+const TOLERANCE: f32 = 0.0001; // world units, a particle is 0.02 across
+
+/// --compare-backends: runs the same ticks through the compute shader and sand::cpu and reports
+/// every particle that ended up somewhere different. Returns whether they agreed.
+pub fn run(
+    world: Vec<Padded<Material, PADDING>>,
+    pool: &ParticlePool,
+    hitboxes: Vec<Hitbox>,
+    registry: &MaterialRegistry,
+    settings: WorldSettings,
+    terrain: &Terrain,
+    seed: u32,
+    ticks: u32,
+) -> bool {
+    let Some((device, queue)) = gpu_constructor::construct_headless() else {
+        println!("no vulkan device, nothing to compare the cpu backend against");
+        return true;
+    };
+    let memory_allocator = StandardMemoryAllocator::new_default(device.clone());
+    let command_buffer_allocator =
+        StandardCommandBufferAllocator::new(device.clone(), Default::default());
+
+    let world_buffer = sand::upload_device_buffer(&memory_allocator, pool.capacity as u64);
+    let world_buffer_swap = sand::upload_device_buffer(&memory_allocator, pool.capacity as u64);
+    let (grid_buffer, sorted_buffer) =
+        sand::upload_grid_buffers(&memory_allocator, pool.capacity as u64);
+    let hitbox_buffer = upload_standard_buffer(
+        hitboxes
+            .iter()
+            .map(|h| Padded::<Hitbox, HITBOX_PADDING>(*h))
+            .collect(),
+        &memory_allocator,
+    );
+    let impulse_buffer = sand::upload_impulse_buffer(&memory_allocator, hitbox_buffer.len());
+    let contacts_buffer = sand::upload_contacts_buffer(
+        &memory_allocator,
+        hitbox_buffer.len(),
+        registry.types.len() as u64,
+    );
+    let material_types_buffer = upload_standard_buffer(registry.gpu_types(), &memory_allocator);
+    let reactions_buffer = upload_standard_buffer(registry.reactions.clone(), &memory_allocator);
+    let settings_buffer = sand::upload_uniform_buffer(settings, &memory_allocator);
+    let count_buffer = sand::upload_uniform_buffer(pool.next, &memory_allocator);
+    let clock_buffer = sand::upload_uniform_buffer(Clock { seed, tick: 0 }, &memory_allocator);
+    let killed_buffer = sand::upload_killed_buffer(&memory_allocator, pool.capacity);
+    let terrain_image = sand::upload_terrain_image(
+        &memory_allocator,
+        &command_buffer_allocator,
+        &queue,
+        terrain,
+    );
+
+    let mut cpu_world: Vec<Material> = world.iter().map(|m| **m).collect();
+    sand::upload_world_buffer(
+        &memory_allocator,
+        &command_buffer_allocator,
+        &queue,
+        &world_buffer,
+        cpu_world.clone(),
+    );
+    let shader = sand::sand_shader::load(device.clone()).expect("Failed to create compute shader.");
+    let command = Arc::new(deploy_shader::get_deploy_command(
+        &shader,
+        &device,
+        &queue,
+        &world_buffer,
+        &world_buffer_swap,
+        &hitbox_buffer,
+        &grid_buffer,
+        &sorted_buffer,
+        &material_types_buffer,
+        &reactions_buffer,
+        &settings_buffer,
+        &count_buffer,
+        &clock_buffer,
+        &killed_buffer,
+        &impulse_buffer,
+        &contacts_buffer,
+        &terrain_image,
+        [pool.capacity / 64, 1, 1],
+    ));
+    for tick in 0..ticks {
+        *clock_buffer.write().unwrap() = Clock { seed, tick };
+        sand::tick(&device, &queue, command.clone())
+            .wait(None)
+            .unwrap();
+        sand::drain_killed(&killed_buffer); // alive is compared below, the list just can't overflow
+        sand::drain_contacts(&contacts_buffer, registry.types.len());
+    }
+    let gpu_world = sand::download_world_buffer(
+        &memory_allocator,
+        &command_buffer_allocator,
+        &queue,
+        &world_buffer,
+    );
+
+    let mut cpu_hitboxes = hitboxes;
+    for tick in 0..ticks {
+        cpu::tick(
+            &mut cpu_world,
+            &mut cpu_hitboxes,
+            registry,
+            &settings,
+            terrain,
+            &Clock { seed, tick },
+            pool.next as usize,
+        );
+    }
+
+    let mut worst = 0f32;
+    let mut drifted = 0;
+    let mut changed = 0;
+    for (idx, (on_gpu, on_cpu)) in gpu_world
+        .iter()
+        .zip(&cpu_world)
+        .enumerate()
+        .take(pool.next as usize)
+    {
+        if on_gpu.alive != on_cpu.alive || on_gpu.id != on_cpu.id {
+            println!(
+                "particle {idx}: gpu is material {}, cpu is {}",
+                on_gpu.id, on_cpu.id
+            );
+            changed += 1;
+            continue;
+        }
+        let error = (Vec2::from(on_gpu.pos) - Vec2::from(on_cpu.pos)).length();
+        worst = worst.max(error);
+        if error > TOLERANCE {
+            println!(
+                "particle {idx}: gpu at {:?}, cpu at {:?}",
+                on_gpu.pos, on_cpu.pos
+            );
+            drifted += 1;
+        }
+    }
+    let mut pushed = 0;
+    for (idx, (on_gpu, on_cpu)) in hitbox_buffer
+        .read()
+        .unwrap()
+        .iter()
+        .zip(&cpu_hitboxes)
+        .enumerate()
+    {
+        // fixed point sums don't depend on order, only on where the grains were
+        if (Vec2::from(on_gpu.vel) - Vec2::from(on_cpu.vel)).length() > TOLERANCE {
+            println!(
+                "hitbox {idx}: gpu moving {:?}, cpu moving {:?}",
+                on_gpu.vel, on_cpu.vel
+            );
+            pushed += 1;
+        }
+    }
+    println!(
+        "{ticks} ticks of {} particles: worst position error {worst}, {drifted} past {TOLERANCE}, {changed} turned into different materials, {pushed} hitboxes pushed differently",
+        pool.next
+    );
+    drifted == 0 && changed == 0 && pushed == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::sand::sand_shader::{MaterialType, Reaction};
+
+    #[test]
+    fn gpu_matches_cpu() {
+        if gpu_constructor::construct_headless().is_none() {
+            return; // nothing to compare the cpu backend against
+        }
+        let mut world: Vec<Padded<Material, PADDING>> = (0..200)
+            .map(|i| {
+                let pos = [0.3 + (i % 20) as f32 * 0.02, 0.3 + (i / 20) as f32 * 0.02];
+                Padded(Material {
+                    pos,
+                    target: pos,
+                    ..Default::default()
+                })
+            })
+            .collect();
+        let pool = ParticlePool::pad(&mut world);
+        let registry = MaterialRegistry {
+            types: vec![MaterialType::default()],
+            reactions: vec![Reaction {
+                tag: 0,
+                touching: 0,
+                into: 0,
+                chance: 0.0,
+            }], // buffers can't be empty
+            ..Default::default()
+        };
+        let hitboxes = vec![Hitbox {
+            pos: [0.4, 0.55],
+            size: [0.2, 0.05],
+            ..Default::default()
+        }];
+        assert!(run(
+            world,
+            &pool,
+            hitboxes,
+            &registry,
+            WorldSettings::default(),
+            &Terrain::empty(),
+            7,
+            10,
+        ));
+    }
+}
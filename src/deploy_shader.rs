@@ -1,4 +1,10 @@
-use crate::simulation::sand::sand_shader::Hitbox;
+use crate::simulation::sand::sand_shader::{
+    Clock, GridCell, Hitbox, MaterialType, Reaction, StagePush, WorldSettings,
+};
+use crate::simulation::sand::{
+    GRID_CELLS, HITBOX_PADDING, STAGE_CLEAR, STAGE_COUNT, STAGE_REDUCE, STAGE_SCAN, STAGE_SCATTER,
+    STAGE_SIMULATE, STAGE_SORT, TYPE_PADDING,
+};
 use crate::sync::future::FenceSignalFuture;
 use crate::sync::future::NowFuture;
 use std::sync::Arc;
@@ -12,8 +18,11 @@ use vulkano::command_buffer::{AutoCommandBufferBuilder, CommandBufferUsage};
 use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
 use vulkano::descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet};
 use vulkano::device::{Device, Queue};
+use vulkano::image::view::ImageView;
+use vulkano::image::ImmutableImage;
 use vulkano::padded::Padded;
 use vulkano::pipeline::{ComputePipeline, Pipeline, PipelineBindPoint};
+use vulkano::sampler::Sampler;
 use vulkano::shader::ShaderModule;
 use vulkano::sync::{self, GpuFuture};
 
@@ -34,7 +43,19 @@ pub fn get_deploy_command<T>(
     device: &Arc<Device>,
     queue: &Arc<Queue>,
     buffer_particle: &Subbuffer<[T]>,
-    buffer_hit: &Subbuffer<[Padded<Hitbox, 4>]>,
+    buffer_swap: &Subbuffer<[T]>,
+    buffer_hit: &Subbuffer<[Padded<Hitbox, HITBOX_PADDING>]>,
+    buffer_cells: &Subbuffer<[GridCell]>,
+    buffer_sorted: &Subbuffer<[u32]>,
+    buffer_types: &Subbuffer<[Padded<MaterialType, TYPE_PADDING>]>,
+    buffer_reactions: &Subbuffer<[Reaction]>,
+    buffer_settings: &Subbuffer<WorldSettings>,
+    buffer_count: &Subbuffer<u32>,
+    buffer_clock: &Subbuffer<Clock>,
+    buffer_killed: &Subbuffer<[u32]>,
+    buffer_impulse: &Subbuffer<[i32]>,
+    buffer_contacts: &Subbuffer<[i32]>,
+    terrain: &(Arc<ImageView<ImmutableImage>>, Arc<Sampler>),
     work_group_counts: [u32; 3],
 ) -> vulkano::command_buffer::PrimaryAutoCommandBuffer {
     let compute_pipeline = ComputePipeline::new(
@@ -54,17 +75,36 @@ pub fn get_deploy_command<T>(
         .get(descriptor_set_layout_index)
         .unwrap();
 
-    let descriptor_set = match PersistentDescriptorSet::new(
-        &descriptor_set_allocator,
-        descriptor_set_layout.clone(),
-        [
-            WriteDescriptorSet::buffer(0, buffer_particle.clone()),
-            WriteDescriptorSet::buffer(1, buffer_hit.clone()),
-        ], // 0-1 is the binding
-    ) {
-        Ok(res) => res,
-        Err(e) => panic!("Error with {e:?}"),
-    };
+    // one set per direction, substeps alternate so buffer_particle always ends up holding the result
+    let descriptor_sets = [
+        (buffer_particle, buffer_swap),
+        (buffer_swap, buffer_particle),
+    ]
+    .map(|(read, write)| {
+        match PersistentDescriptorSet::new(
+            &descriptor_set_allocator,
+            descriptor_set_layout.clone(),
+            [
+                WriteDescriptorSet::buffer(0, read.clone()),
+                WriteDescriptorSet::buffer(1, write.clone()),
+                WriteDescriptorSet::buffer(2, buffer_hit.clone()),
+                WriteDescriptorSet::buffer(3, buffer_cells.clone()),
+                WriteDescriptorSet::buffer(4, buffer_sorted.clone()),
+                WriteDescriptorSet::buffer(5, buffer_types.clone()),
+                WriteDescriptorSet::buffer(6, buffer_reactions.clone()),
+                WriteDescriptorSet::buffer(7, buffer_settings.clone()),
+                WriteDescriptorSet::buffer(8, buffer_count.clone()),
+                WriteDescriptorSet::buffer(9, buffer_clock.clone()),
+                WriteDescriptorSet::buffer(10, buffer_killed.clone()),
+                WriteDescriptorSet::image_view_sampler(11, terrain.0.clone(), terrain.1.clone()),
+                WriteDescriptorSet::buffer(12, buffer_impulse.clone()),
+                WriteDescriptorSet::buffer(13, buffer_contacts.clone()),
+            ], // 0-13 is the binding
+        ) {
+            Ok(res) => res,
+            Err(e) => panic!("Error with {e:?}"),
+        }
+    });
 
     let command_buffer_allocator = StandardCommandBufferAllocator::new(
         device.clone(),
@@ -77,15 +117,49 @@ pub fn get_deploy_command<T>(
     )
     .unwrap();
 
-    command_buffer_builder
-        .bind_pipeline_compute(compute_pipeline.clone())
-        .bind_descriptor_sets(
+    command_buffer_builder.bind_pipeline_compute(compute_pipeline.clone());
+
+    // rebuild the spatial hash then simulate, the builder inserts the barriers between stages
+    let cell_group_counts = [(GRID_CELLS + 63) / 64, 1, 1];
+    for (substep, descriptor_set) in descriptor_sets.into_iter().enumerate() {
+        command_buffer_builder.bind_descriptor_sets(
             PipelineBindPoint::Compute,
             compute_pipeline.layout().clone(),
             descriptor_set_layout_index as u32,
             descriptor_set,
+        );
+        for (stage, group_counts) in [
+            (STAGE_CLEAR, cell_group_counts),
+            (STAGE_COUNT, work_group_counts),
+            (STAGE_SCAN, [1, 1, 1]),
+            (STAGE_SCATTER, work_group_counts),
+            (STAGE_SORT, cell_group_counts),
+            (STAGE_SIMULATE, work_group_counts),
+        ] {
+            command_buffer_builder
+                .push_constants(
+                    compute_pipeline.layout().clone(),
+                    0,
+                    StagePush {
+                        stage,
+                        substep: substep as u32,
+                    },
+                )
+                .dispatch(group_counts)
+                .unwrap();
+        }
+    }
+    // once per tick, both substeps pushed entities into the same accumulator
+    command_buffer_builder
+        .push_constants(
+            compute_pipeline.layout().clone(),
+            0,
+            StagePush {
+                stage: STAGE_REDUCE,
+                substep: 1,
+            },
         )
-        .dispatch(work_group_counts)
+        .dispatch([(buffer_hit.len() as u32 + 63) / 64, 1, 1])
         .unwrap();
 
     command_buffer_builder.build().unwrap()
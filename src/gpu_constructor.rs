@@ -63,13 +63,7 @@ pub fn construct_gpu() -> (
                 })
                 .map(|q| (p, q as u32))
         })
-        .min_by_key(|(p, _)| match p.properties().device_type {
-            PhysicalDeviceType::DiscreteGpu => 0,
-            PhysicalDeviceType::IntegratedGpu => 1,
-            PhysicalDeviceType::VirtualGpu => 2,
-            PhysicalDeviceType::Cpu => 3,
-            _ => 4,
-        })
+        .min_by_key(|(p, _)| device_preference(p))
         .expect("no device available");
     let name = &physical_device.properties().device_name;
     println!("{name:?}");
@@ -122,3 +116,47 @@ pub fn construct_gpu() -> (
 		window.inner_size(),
     )
 }
+
+/// Compute only device for --compare-backends, None when there is no vulkan implementation to use.
+pub fn construct_headless() -> Option<(Arc<Device>, Arc<Queue>)> {
+    let library = VulkanLibrary::new().ok()?;
+    let instance = Instance::new(library, InstanceCreateInfo::default()).ok()?;
+    let (physical_device, queue_family_index) = instance
+        .enumerate_physical_devices()
+        .ok()?
+        .filter_map(|p| {
+            p.queue_family_properties()
+                .iter()
+                .position(|q| q.queue_flags.contains(QueueFlags::COMPUTE))
+                .map(|q| (p, q as u32))
+        })
+        .min_by_key(|(p, _)| device_preference(p))?; // software devices like lavapipe are fine here
+    let name = &physical_device.properties().device_name;
+    println!("{name:?}");
+    let (device, mut queues) = Device::new(
+        physical_device,
+        DeviceCreateInfo {
+            queue_create_infos: vec![QueueCreateInfo {
+                queue_family_index,
+                ..Default::default()
+            }],
+            enabled_extensions: DeviceExtensions {
+                khr_storage_buffer_storage_class: true,
+                ..DeviceExtensions::empty()
+            },
+            ..Default::default()
+        },
+    )
+    .ok()?;
+    Some((device, queues.next()?))
+}
+
+fn device_preference(device: &PhysicalDevice) -> u32 {
+    match device.properties().device_type {
+        PhysicalDeviceType::DiscreteGpu => 0,
+        PhysicalDeviceType::IntegratedGpu => 1,
+        PhysicalDeviceType::VirtualGpu => 2,
+        PhysicalDeviceType::Cpu => 3,
+        _ => 4,
+    }
+}
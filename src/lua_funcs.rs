@@ -1,52 +1,327 @@
 use rlua::{
-    Context, MultiValue, Table, ToLuaMulti,
-    Value::{self, Nil},
+    Context, FromLua, FromLuaMulti, Function, MetaMethod, MultiValue, Table, ToLuaMulti, UserData,
+    UserDataMethods, Value,
 };
 
-use crate::simulation::ecs::Entity;
+use std::fs;
+use std::sync::{Arc, Mutex};
 
-pub fn create(lua_ctx: Context, entities: Vec<Entity>, frame: usize, time: u128) {
+use crate::simulation::ecs::{Collision, Command, Component, Entity};
+use crate::simulation::materials::MaterialRegistry;
+use crate::simulation::sand::{self, sand_shader::WorldSettings, ParticlePool};
+
+/// Engine state scripts are allowed to change, the window picks changes up after every tick.
+pub struct Controls {
+    pub settings: WorldSettings,
+    pub settings_changed: bool,
+    pub paused: bool,     // physics stops, rendering and tick.lua keep going
+    pub step_frames: u32, // ticks still to run while paused, one per frame
+    pub time_scale: f32,  // simulated seconds per real second
+    pub registry: Arc<MaterialRegistry>, // shared so bindings can read tables without the lock
+    pub pool: ParticlePool,
+    pub commands: Vec<Command>, // entity edits from this frame's scripts, see ecs::regenerate
+    pub reload: bool,           // reload the scripts in window::reload::RELOADABLE next frame
+}
+
+impl Controls {
+    pub fn new(
+        settings: WorldSettings,
+        registry: MaterialRegistry,
+        pool: ParticlePool,
+    ) -> Arc<Mutex<Controls>> {
+        Arc::new(Mutex::new(Controls {
+            settings,
+            settings_changed: false,
+            paused: false,
+            step_frames: 0,
+            time_scale: 1f32,
+            registry: Arc::new(registry),
+            pool,
+            commands: Vec::new(),
+            reload: false,
+        }))
+    }
+
+    /// Everything but entity edits a script can change, so a tick that errors can be undone whole.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            settings: self.settings,
+            settings_changed: self.settings_changed,
+            paused: self.paused,
+            step_frames: self.step_frames,
+            time_scale: self.time_scale,
+            pool: self.pool.clone(),
+        }
+    }
+
+    pub fn restore(&mut self, checkpoint: Checkpoint) {
+        self.settings = checkpoint.settings;
+        self.settings_changed = checkpoint.settings_changed;
+        self.paused = checkpoint.paused;
+        self.step_frames = checkpoint.step_frames;
+        self.time_scale = checkpoint.time_scale;
+        self.pool = checkpoint.pool;
+    }
+}
+
+/// See Controls::checkpoint, the registry and reload flag aren't script writable.
+pub struct Checkpoint {
+    settings: WorldSettings,
+    settings_changed: bool,
+    paused: bool,
+    step_frames: u32,
+    time_scale: f32,
+    pool: ParticlePool,
+}
+
+/// Compiles a script named after its path, so errors read `./data/tick.lua:12: ...`.
+pub fn load_script<'lua>(lua_ctx: Context<'lua>, path: &str) -> rlua::Result<Function<'lua>> {
+    let content = fs::read_to_string(path)
+        .map_err(|error| rlua::Error::RuntimeError(format!("{path}: {error}")))?;
+    lua_ctx
+        .load(&content[..])
+        .set_name(&format!("@{path}"))?
+        .into_function()
+}
+
+/// Runs a startup script for what it returns, there's nothing to fall back on so a broken one
+/// is reported and the engine quits.
+pub fn eval_script<'lua, R: FromLuaMulti<'lua>>(lua_ctx: Context<'lua>, path: &str) -> R {
+    exit_on_error(load_script(lua_ctx, path).and_then(|script| script.call(())))
+}
+
+/// For startup, where a script error leaves nothing to run.
+pub fn exit_on_error<T>(result: rlua::Result<T>) -> T {
+    result.unwrap_or_else(|error| {
+        eprintln!("{}", describe(&error));
+        std::process::exit(1)
+    })
+}
+
+/// `{ 1, 2 }` style vectors from `table[name]`, None when it isn't set.
+pub fn read_vec<const N: usize>(table: &Table, name: &str) -> rlua::Result<Option<[f32; N]>> {
+    let invalid = || rlua::Error::RuntimeError(format!("{name} must be a list of {N} numbers"));
+    let values = match table.get::<&str, Value>(name)? {
+        Value::Nil => return Ok(None),
+        Value::Table(values) => values,
+        _ => return Err(invalid()),
+    };
+    let mut vals = [0f32; N];
+    for elem in values.pairs::<usize, f32>() {
+        let (key, value) = elem.map_err(|_| invalid())?;
+        *vals.get_mut(key.wrapping_sub(1)).ok_or_else(invalid)? = value;
+    }
+    Ok(Some(vals))
+}
+
+/// Errors raised by the bindings only know their script line through the traceback.
+pub fn describe(error: &rlua::Error) -> String {
+    match error {
+        rlua::Error::CallbackError { traceback, cause } => format!("{cause}\n{traceback}"),
+        error => error.to_string(),
+    }
+}
+
+/// What scripts see of the current frame, ecs::regenerate refreshes it before tick.lua runs.
+pub struct FrameInfo {
+    pub frame: usize,
+    pub time: u128,
+    pub delta: f64,
+    pub collisions: Vec<Collision>,
+}
+
+impl FrameInfo {
+    pub fn new(time: u128) -> Arc<Mutex<FrameInfo>> {
+        Arc::new(Mutex::new(FrameInfo {
+            frame: 0,
+            time,
+            delta: 0f64,
+            collisions: Vec::new(),
+        }))
+    }
+}
+
+/// Registers every binding once, they only hold the shared state so later frames just update it.
+/// Bindings never lock while lua runs and take controls before entities when they need both.
+pub fn create(
+    lua_ctx: Context,
+    entities: Arc<Mutex<Vec<Entity>>>,
+    frame_info: Arc<Mutex<FrameInfo>>,
+    controls: Arc<Mutex<Controls>>,
+) {
     let globals = lua_ctx.globals();
 
-    let e_1 = entities.clone();
+    let entities_clone = entities.clone();
     let temp_fn = lua_ctx
-        .create_function(move |lua_ctx, _: rlua::Value| Result::Ok(get_entities(&lua_ctx, &e_1)))
+        .create_function(move |lua_ctx, _: rlua::Value| {
+            Result::Ok(get_entities(&lua_ctx, &entities_clone.lock().unwrap()))
+        })
         .unwrap();
     globals.set("GetEntities", temp_fn).unwrap();
 
+    let info = frame_info.clone();
     let temp_fn = lua_ctx
-        .create_function(move |_, _: Value| Result::Ok(get_cur_frame(frame)))
+        .create_function(move |_, _: Value| Result::Ok(get_cur_frame(info.lock().unwrap().frame)))
         .unwrap();
     globals.set("GetFrame", temp_fn).unwrap();
 
+    let info = frame_info.clone();
     let temp_fn = lua_ctx
-        .create_function(move |_, _: Value| Result::Ok(get_cur_time(time)))
+        .create_function(move |_, _: Value| Result::Ok(get_cur_time(info.lock().unwrap().time)))
         .unwrap();
     globals.set("GetTime", temp_fn).unwrap();
 
+    let info = frame_info.clone();
     let temp_fn = lua_ctx
-        .create_function(move |lua_ctx, (id, path, value): (usize, String, Table)| {
-            set_entity_value(lua_ctx, id, path, value);
-            Result::Ok(())
+        .create_function(move |_, _: Value| Result::Ok(info.lock().unwrap().delta))
+        .unwrap();
+    globals.set("GetDeltaTime", temp_fn).unwrap(); // simulated seconds since the last tick.lua run
+
+    let entities_clone = entities.clone();
+    let controls_clone = controls.clone();
+    let temp_fn = lua_ctx
+        .create_function(move |_, (id, path, value): (usize, String, Table)| {
+            check_id(&entities_clone, id)?;
+            set_entity_value(&controls_clone, id, path, value)
         })
         .unwrap();
     globals.set("EntitySetComponentValue", temp_fn).unwrap();
 
-    let entities_clone = entities.clone(); // hackery to move to two different closures.
+    let entities_clone = entities.clone();
+    let controls_clone = controls.clone();
     let temp_fn = lua_ctx
         .create_function(move |lua_ctx, (id, path): (usize, String)| {
-            get_entity_value(lua_ctx, &entities_clone, id, path).to_lua_multi(lua_ctx)
+            let controls = controls_clone.lock().unwrap();
+            let entities = entities_clone.lock().unwrap();
+            get_entity_value(lua_ctx, &entities, &controls.commands, id, path)?
+                .to_lua_multi(lua_ctx)
         })
         .unwrap();
     globals.set("EntityGetComponentValue", temp_fn).unwrap();
 
+    let entities_clone = entities.clone();
+    let controls_clone = controls.clone();
+    let temp_fn = lua_ctx
+        .create_function(move |_, id: usize| {
+            check_id(&entities_clone, id)?;
+            Result::Ok(EntityRef {
+                id,
+                path: String::new(),
+                entities: entities_clone.clone(),
+                controls: controls_clone.clone(),
+            })
+        })
+        .unwrap();
+    globals.set("GetEntity", temp_fn).unwrap(); // e.hitbox.vel.x = 1, see EntityRef
+
+    let controls_clone = controls.clone();
+    let temp_fn = lua_ctx
+        .create_function(move |_, _: Value| {
+            let mut controls = controls_clone.lock().unwrap();
+            Result::Ok(create_entity(&mut controls, &entities.lock().unwrap()))
+        })
+        .unwrap();
+    globals.set("CreateEntity", temp_fn).unwrap(); // returns the new id or nil when full, usable next frame
+
+    let controls_clone = controls.clone();
     let temp_fn = lua_ctx
-        .create_function(move |lua_ctx, _: Value| {
-            create_entity(lua_ctx, &entities);
+        .create_function(move |_, paused: bool| {
+            controls_clone.lock().unwrap().paused = paused;
             Result::Ok(())
         })
         .unwrap();
-    globals.set("CreateEntity", temp_fn).unwrap();
+    globals.set("SetPaused", temp_fn).unwrap();
+
+    let controls_clone = controls.clone();
+    let temp_fn = lua_ctx
+        .create_function(move |_, frames: u32| {
+            let mut controls = controls_clone.lock().unwrap();
+            controls.paused = true; // stepping only makes sense when stopped
+            controls.step_frames = controls.step_frames.saturating_add(frames);
+            Result::Ok(())
+        })
+        .unwrap();
+    globals.set("StepFrames", temp_fn).unwrap();
+
+    let controls_clone = controls.clone();
+    let temp_fn = lua_ctx
+        .create_function(move |_, scale: f32| {
+            controls_clone.lock().unwrap().time_scale = scale.max(0f32);
+            Result::Ok(())
+        })
+        .unwrap();
+    globals.set("SetTimeScale", temp_fn).unwrap();
+
+    let controls_clone = controls.clone();
+    let temp_fn = lua_ctx
+        .create_function(move |_, value: Table| {
+            let registry = controls_clone.lock().unwrap().registry.clone();
+            let particle = registry.particle(&value)?; // unlocked, the table's metamethods may call bindings
+            Result::Ok(controls_clone.lock().unwrap().pool.spawn(particle))
+        })
+        .unwrap();
+    globals.set("SpawnParticle", temp_fn).unwrap(); // same table as init_world.lua, returns the slot
+
+    let controls_clone = controls.clone();
+    let temp_fn = lua_ctx
+        .create_function(move |_, idx: u32| {
+            Result::Ok(controls_clone.lock().unwrap().pool.destroy(idx))
+        })
+        .unwrap();
+    globals.set("DestroyParticle", temp_fn).unwrap();
+
+    let controls_clone = controls.clone();
+    let temp_fn = lua_ctx
+        .create_function(move |lua_ctx, entity: Option<usize>| {
+            let controls = controls_clone.lock().unwrap();
+            let table = lua_ctx.create_table().unwrap();
+            for (key, collision) in frame_info
+                .lock()
+                .unwrap()
+                .collisions
+                .iter()
+                .filter(|c| entity.map_or(true, |entity| c.entity == entity))
+                .enumerate()
+            {
+                let collision = collision_table(lua_ctx, collision, &controls.registry);
+                table.set(key + 1, collision).unwrap();
+            }
+            Result::Ok(table)
+        })
+        .unwrap();
+    globals.set("GetCollisions", temp_fn).unwrap(); // this frame's, nil for every entity
+
+    let temp_fn = lua_ctx
+        .create_function(move |_, values: Table| {
+            let mut settings = controls.lock().unwrap().settings;
+            sand::apply_world_settings(&mut settings, &values)?; // unlocked, like SpawnParticle
+            let mut controls = controls.lock().unwrap();
+            controls.settings = settings;
+            controls.settings_changed = true;
+            Result::Ok(())
+        })
+        .unwrap();
+    globals.set("SetPhysics", temp_fn).unwrap();
+}
+
+/// `{ entity, material = "sand" or nil, other = entity or nil, impulse = { x, y } }`, the table
+/// OnCollision and GetCollisions hand out.
+pub fn collision_table<'a>(
+    lua_ctx: Context<'a>,
+    collision: &Collision,
+    registry: &MaterialRegistry,
+) -> Table<'a> {
+    let table = lua_ctx.create_table().unwrap();
+    table.set("entity", collision.entity).unwrap();
+    if let Some(id) = collision.material {
+        table.set("material", registry.name(id)).unwrap();
+    }
+    table.set("other", collision.other).unwrap();
+    let impulse = lua_ctx.create_table().unwrap();
+    impulse.set("x", collision.impulse[0]).unwrap();
+    impulse.set("y", collision.impulse[1]).unwrap();
+    table.set("impulse", impulse).unwrap();
+    table
 }
 
 fn get_entities<'a>(lua_ctx: &Context<'a>, entities: &[Entity]) -> Table<'a> {
@@ -70,37 +345,179 @@ fn get_cur_time(time: u128) -> u128 {
     time
 }
 
-fn set_entity_value(lua_ctx: Context, id: usize, path: String, values: Table) {
-    let mut cmd = "RS_deltas = RS_deltas or {};table.insert(RS_deltas,{".to_owned();
-    // println!("{path:?}");
-	let v_typer = values.get::<usize, String>(1);
-    let value1 = &if v_typer.is_err() {
-        if values.get::<usize, bool>(1).unwrap() {
-            "true".to_owned()
-        } else {
-            "false".to_owned()
-        } // others get type coerced
+fn check_id(entities: &Mutex<Vec<Entity>>, id: usize) -> rlua::Result<()> {
+    if id < entities.lock().unwrap().len() {
+        Ok(())
     } else {
-		println!("stringy");
-        values.get::<usize, String>(1).unwrap()
-    }[..];
-    let value2 = &(if values.len().unwrap() == 2 {
-        values.get::<usize, String>(2).unwrap()
-    } else {
-        "0".to_owned()
-    })[..];
-    cmd.push_str(&id.to_string()[..]);
-    cmd.push_str(",\"");
-    cmd.push_str(&path[..]);
-    cmd.push_str("\",");
-    cmd.push_str(value1);
-    cmd.push(',');
-    cmd.push_str(value2);
-    cmd.push_str("})");
-    // println!("cmd");
-    // println!("{cmd:?}");
-    lua_ctx.load(&cmd[..]).exec().unwrap();
-    // println!("cont");
+        Err(rlua::Error::RuntimeError(format!("no entity {id}")))
+    }
+}
+
+fn set_entity_value(
+    controls: &Mutex<Controls>,
+    id: usize,
+    path: String,
+    values: Table,
+) -> rlua::Result<()> {
+    let component = Component::parse(&path, &values)?;
+    controls
+        .lock()
+        .unwrap()
+        .commands
+        .push(Command::Set(id, component));
+    Ok(())
+}
+
+fn get_entity_value<'a>(
+    lua_ctx: Context<'a>,
+    entities: &[Entity],
+    commands: &[Command],
+    id: usize,
+    path: String,
+) -> rlua::Result<EntityData<'a>> {
+    let component = current_component(entities, commands, id, &path)
+        .ok_or_else(|| rlua::Error::RuntimeError(format!("invalid path {path} on entity {id}")))?;
+    Ok(component_data(lua_ctx, component))
+}
+
+/// What `path` reads as for scripts, edits earlier this frame haven't been applied yet so the
+/// last one wins over the stored value.
+fn current_component(
+    entities: &[Entity],
+    commands: &[Command],
+    id: usize,
+    path: &str,
+) -> Option<Component> {
+    let mut component = Component::read(entities.get(id)?, path)?;
+    for command in commands {
+        if let Command::Set(eid, edit) = command {
+            if *eid == id && edit.path() == path {
+                component = edit.clone();
+            }
+        }
+    }
+    Some(component)
+}
+
+fn component_data(lua_ctx: Context, component: Component) -> EntityData {
+    if let Some([x, y]) = component.vec2() {
+        let table = lua_ctx.create_table().unwrap();
+        table.set("x", x).unwrap();
+        table.set("y", y).unwrap();
+        return EntityData::Vec2(table);
+    }
+    match component {
+        Component::HitboxMass(v) | Component::HitboxRadius(v) | Component::HitboxAngle(v) => {
+            EntityData::Float(v)
+        }
+        Component::HitboxShape(v) => match sand::shape_name(v) {
+            Some(name) => EntityData::String(name.to_owned()), // reads back into a write unchanged
+            None => EntityData::Float(v as f32),
+        },
+        Component::HitboxSimulate(v) | Component::Deleted(v) => EntityData::Bool(v),
+        Component::Data(v) => EntityData::String(v),
+        _ => unreachable!("vectors are handled above"),
+    }
+}
+
+/// GetEntity's handle, every index walks one key further down `path` so `e.hitbox.vel.x = 1`
+/// goes "" -> "hitbox" -> "hitbox.vel" -> x. Reads and writes go through the same queue as
+/// EntitySetComponentValue, handles stay valid between runs but the slot may be reused.
+#[derive(Clone)]
+struct EntityRef {
+    id: usize,
+    path: String, // empty for the entity itself
+    entities: Arc<Mutex<Vec<Entity>>>,
+    controls: Arc<Mutex<Controls>>,
+}
+
+impl EntityRef {
+    fn child(&self, key: &str) -> EntityRef {
+        let path = if self.path.is_empty() {
+            key.to_owned()
+        } else {
+            format!("{}.{key}", self.path)
+        };
+        EntityRef {
+            path,
+            ..self.clone()
+        }
+    }
+
+    fn current(&self) -> Option<Component> {
+        let controls = self.controls.lock().unwrap();
+        let entities = self.entities.lock().unwrap();
+        current_component(&entities, &controls.commands, self.id, &self.path)
+    }
+
+    fn invalid(&self, key: &str) -> rlua::Error {
+        rlua::Error::RuntimeError(format!("entity {} has no {}.{key}", self.id, self.path))
+    }
+}
+
+impl UserData for EntityRef {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_meta_method(MetaMethod::Index, |lua_ctx, this, key: String| {
+            if let Some([x, y]) = this.current().and_then(|c| c.vec2()) {
+                return match &key[..] {
+                    "x" => x.to_lua_multi(lua_ctx),
+                    "y" => y.to_lua_multi(lua_ctx),
+                    _ => Err(this.invalid(&key)),
+                };
+            }
+            if this.path.is_empty() && key == "id" {
+                return this.id.to_lua_multi(lua_ctx);
+            }
+            let child = this.child(&key);
+            match child.current() {
+                Some(component) if component.vec2().is_some() => child.to_lua_multi(lua_ctx),
+                Some(component) => component_data(lua_ctx, component).to_lua_multi(lua_ctx),
+                None if child.path == "sprite" || child.path == "hitbox" => {
+                    child.to_lua_multi(lua_ctx)
+                }
+                None => Err(this.invalid(&key)),
+            }
+        });
+        methods.add_meta_method(
+            MetaMethod::NewIndex,
+            |lua_ctx, this, (key, value): (String, Value)| {
+                let component = match this.current() {
+                    Some(component) if component.vec2().is_some() => {
+                        let mut v = component.vec2().unwrap();
+                        match &key[..] {
+                            "x" => v[0] = f32::from_lua(value, lua_ctx)?,
+                            "y" => v[1] = f32::from_lua(value, lua_ctx)?,
+                            _ => return Err(this.invalid(&key)),
+                        }
+                        component.with_vec2(v)
+                    }
+                    _ => match this.child(&key).current() {
+                        Some(component) => component.set(lua_ctx, value)?,
+                        None => return Err(this.invalid(&key)),
+                    },
+                };
+                let mut controls = this.controls.lock().unwrap();
+                controls.commands.push(Command::Set(this.id, component));
+                Ok(())
+            },
+        );
+    }
+}
+
+/// Claims the first deleted slot nothing else claimed this frame, regenerate clears it.
+/// None when every slot is taken, nothing is queued then.
+fn create_entity(controls: &mut Controls, entities: &[Entity]) -> Option<usize> {
+    let claimed = |key: usize, commands: &[Command]| {
+        commands
+            .iter()
+            .any(|command| matches!(command, Command::Create(eid) if *eid == key))
+    };
+    let idx = entities
+        .iter()
+        .enumerate()
+        .position(|(key, entity)| entity.deleted && !claimed(key, &controls.commands))?;
+    controls.commands.push(Command::Create(idx));
+    Some(idx)
 }
 
 enum EntityData<'a> {
@@ -108,7 +525,6 @@ enum EntityData<'a> {
     Float(f32),
     Bool(bool),
     String(String),
-    Nil(),
 }
 
 impl ToLuaMulti<'_> for EntityData<'_> {
@@ -127,196 +543,6 @@ impl ToLuaMulti<'_> for EntityData<'_> {
             EntityData::Float(v) => rlua::Result::Ok(v.to_lua_multi(lua).unwrap()),
             EntityData::Bool(v) => rlua::Result::Ok(v.to_lua_multi(lua).unwrap()),
             EntityData::String(v) => rlua::Result::Ok(v.to_lua_multi(lua).unwrap()),
-            EntityData::Nil() => rlua::Result::Ok(Nil.to_lua_multi(lua).unwrap()),
         }
     }
 }
-
-fn get_entity_value<'a>(
-    lua_ctx: Context<'a>,
-    entities: &[Entity],
-    id: usize,
-    path: String,
-) -> EntityData<'a> {
-    let RS_deltas = lua_ctx.globals().get("RS_deltas");
-    let mut val = EntityData::Nil();
-    match &path[..] {
-        // code duplication with minor differences, ideally id use macros but no time
-        "sprite.pos" => {
-            let [data1, data2] = entities[id].sprite.pos;
-            let table = lua_ctx.create_table().unwrap();
-            table.set("x", data1).unwrap();
-            table.set("y", data2).unwrap();
-            val = EntityData::Vec2(table);
-        }
-        "sprite.size" => {
-            let [data1, data2] = entities[id].sprite.size;
-            let table = lua_ctx.create_table().unwrap();
-            table.set("x", data1).unwrap();
-            table.set("y", data2).unwrap();
-            val = EntityData::Vec2(table);
-        }
-        "sprite.offset" => {
-            let [data1, data2] = entities[id].sprite.offset;
-            let table = lua_ctx.create_table().unwrap();
-            table.set("x", data1).unwrap();
-            table.set("y", data2).unwrap();
-            val = EntityData::Vec2(table);
-        }
-        "sprite.scale" => {
-            let [data1, data2] = entities[id].sprite.scale;
-            let table = lua_ctx.create_table().unwrap();
-            table.set("x", data1).unwrap();
-            table.set("y", data2).unwrap();
-            val = EntityData::Vec2(table);
-        }
-        "hitbox.pos" => {
-            let [data1, data2] = entities[id].hitbox.pos;
-            let table = lua_ctx.create_table().unwrap();
-            table.set("x", data1).unwrap();
-            table.set("y", data2).unwrap();
-            val = EntityData::Vec2(table);
-        }
-        "hitbox.size" => {
-            let [data1, data2] = entities[id].hitbox.size;
-            let table = lua_ctx.create_table().unwrap();
-            table.set("x", data1).unwrap();
-            table.set("y", data2).unwrap();
-            val = EntityData::Vec2(table);
-        }
-        "hitbox.mass" => {
-            val = EntityData::Float(entities[id].hitbox.mass);
-        }
-        "hitbox.simulate" => {
-            val = EntityData::Bool(entities[id].hitbox.simulate == 1);
-        }
-        "data" => {
-            val = EntityData::String(entities[id].data.clone());
-        }
-        "deleted" => {
-            val = EntityData::Bool(entities[id].deleted);
-        }
-        _ => panic!("invalid path"),
-    }
-    if RS_deltas.is_ok() {
-        let RS_deltas: Table = RS_deltas.unwrap();
-        for elem in RS_deltas.pairs::<usize, Table>() {
-            let (_, value) = elem.unwrap();
-            let eid: usize = value.get(1).unwrap();
-            let cid: String = value.get(2).unwrap();
-            if eid == id && cid == path {
-                match &cid[..] {
-                    "sprite.pos" => {
-                        let data1: f32 = value.get(3).unwrap();
-                        let data2: f32 = value.get(4).unwrap();
-                        let table = lua_ctx.create_table().unwrap();
-                        table.set("x", data1).unwrap();
-                        table.set("y", data2).unwrap();
-                        val = EntityData::Vec2(table);
-                    }
-                    "sprite.size" => {
-                        let data1: f32 = value.get(3).unwrap();
-                        let data2: f32 = value.get(4).unwrap();
-                        let table = lua_ctx.create_table().unwrap();
-                        table.set("x", data1).unwrap();
-                        table.set("y", data2).unwrap();
-                        val = EntityData::Vec2(table);
-                    }
-                    "sprite.offset" => {
-                        let data1: f32 = value.get(3).unwrap();
-                        let data2: f32 = value.get(4).unwrap();
-                        let table = lua_ctx.create_table().unwrap();
-                        table.set("x", data1).unwrap();
-                        table.set("y", data2).unwrap();
-                        val = EntityData::Vec2(table);
-                    }
-                    "sprite.scale" => {
-                        let data1: f32 = value.get(3).unwrap();
-                        let data2: f32 = value.get(4).unwrap();
-                        let table = lua_ctx.create_table().unwrap();
-                        table.set("x", data1).unwrap();
-                        table.set("y", data2).unwrap();
-                        val = EntityData::Vec2(table);
-                    }
-                    "hitbox.pos" => {
-                        let data1: f32 = value.get(3).unwrap();
-                        let data2: f32 = value.get(4).unwrap();
-                        let table = lua_ctx.create_table().unwrap();
-                        table.set("x", data1).unwrap();
-                        table.set("y", data2).unwrap();
-                        val = EntityData::Vec2(table);
-                    }
-                    "hitbox.size" => {
-                        let data1: f32 = value.get(3).unwrap();
-                        let data2: f32 = value.get(4).unwrap();
-                        let table = lua_ctx.create_table().unwrap();
-                        table.set("x", data1).unwrap();
-                        table.set("y", data2).unwrap();
-                        val = EntityData::Vec2(table);
-                    }
-                    "hitbox.mass" => {
-                        let data1: f32 = value.get(3).unwrap();
-                        let data2: f32 = value.get(4).unwrap();
-                        let table = lua_ctx.create_table().unwrap();
-                        table.set("x", data1).unwrap();
-                        table.set("y", data2).unwrap();
-                        val = EntityData::Vec2(table);
-                    }
-                    "hitbox.simulate" => {
-                        let data1 = value.get(3).unwrap();
-                        val = EntityData::Bool(data1);
-                    }
-                    "data" => {
-                        let data1 = value.get(3).unwrap();
-                        val = EntityData::String(data1);
-                    }
-                    "deleted" => {
-                        let data1 = value.get(3).unwrap();
-                        val = EntityData::Bool(data1);
-                    }
-                    _ => {
-                        panic!("invalid path")
-                    }
-                }
-            }
-        }
-    }
-    val
-}
-
-fn create_entity<'a>(lua_ctx: Context<'a>, entities: &[Entity]) {
-    let mut idx = 2u32.pow(30); // if you hit this you have bigger problems
-    for (key, entity) in entities.iter().enumerate() {
-        if entity.deleted {
-            let mut cmd = r"
-			RS_created = RS_created or {}
-			for k,v in ipairs(RS_created) do
-				if k == "
-                .to_owned();
-            cmd.push_str(&key.to_string()[..]);
-            cmd.push_str(
-                r"
-				then
-					return -1 -- on dupe fail
-				end
-			end
-			return 1",
-            );
-            if lua_ctx.load(&cmd[..]).eval::<u32>().unwrap() == 1 {
-                idx = key as u32; // if we find an answer, we are done
-                break;
-            }
-        }
-    }
-    let target = if idx >= 2u32.pow(30)
-    // greater than lol
-    {
-        -1i32
-    } else {
-        idx as i32
-    };
-    let mut cmd = "RS_created = RS_created or {};table.insert(RS_created,".to_owned();
-    cmd.push_str(&target.to_string()[..]);
-    cmd.push(')');
-    lua_ctx.load(&cmd[..]).exec().unwrap();
-}
@@ -1,35 +1,18 @@
-use std::fs;
+use std::time::SystemTime;
 
 use simulation::ecs::Entity;
-use simulation::sand::sand_shader::Hitbox;
+use simulation::materials::MaterialRegistry;
+use simulation::sand::sand_shader::{Hitbox, WorldSettings};
+use simulation::terrain::Terrain;
 use vulkano::buffer::BufferContents;
 
 use vulkano::memory::allocator::{GenericMemoryAllocator, StandardMemoryAllocator};
 use vulkano::padded::Padded;
 use vulkano::sync::{self};
 
-use rlua::{Lua, Table};
-
-mod deploy_shader;
-mod gpu_constructor;
-mod lua_funcs;
-mod pass_structs;
-mod simulation;
-mod window;
-
-use simulation::sand::{sand_shader::Material, PADDING};
-use window::init::fragment_shader::Sprite;
-
-#[derive(BufferContents)]
-#[repr(C)]
-struct TestStruct {
-    first: i32,
-    second: i32,
-    res: i32,
-}
-
-// device, queues,
+use rlua::{Lua, Table, Value};
 
+// defined before the modules so materials.rs can use them too
 macro_rules! handle_lua_elem {
     ($type_of:ty, $name:expr, $dest:ident, $value:expr, $building_mat:expr) => {
         let cv = $value.get::<&str, $type_of>($name);
@@ -50,75 +33,85 @@ macro_rules! handle_lua_elem_spec {
 
 macro_rules! handle_lua_vec {
     ($name:expr, $dest:ident, $count:expr, $value:expr, $building_mat:expr) => {
-        let cv = $value.get::<&str, Table>($name);
-        if cv.is_ok() {
-            let mut vals = [0f32; $count];
-            for elem in cv.unwrap().pairs::<usize, f32>() {
-                let v = elem.unwrap();
-                vals[v.0 - 1] = v.1;
-            }
+        let cv = crate::lua_funcs::read_vec::<$count>(&$value, $name);
+        if let Some(vals) = crate::lua_funcs::exit_on_error(cv) {
             $building_mat.$dest = vals;
         }
     };
 }
 
+mod compare;
+mod deploy_shader;
+mod gpu_constructor;
+mod lua_funcs;
+mod pass_structs;
+mod simulation;
+mod window;
+
+use simulation::sand::{self, sand_shader::Material, Backend, ParticlePool, PADDING};
+use window::init::fragment_shader::Sprite;
+
+#[derive(BufferContents)]
+#[repr(C)]
+struct TestStruct {
+    first: i32,
+    second: i32,
+    res: i32,
+}
+
+// device, queues,
+
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let backend = arg_value(&args, "--backend")
+        .flatten()
+        .map_or(Backend::Gpu, |name| {
+            sand::backend_from_name(name).unwrap_or_else(|| panic!("unknown backend {name}"))
+        });
     let lua_obj = Lua::new();
     let mut world: Vec<Padded<Material, PADDING>> = Vec::new();
+    let mut registry = MaterialRegistry::default();
+    let mut settings = WorldSettings::default();
+    let mut max_catch_up = 0;
+    let mut seed = 0;
 
     lua_obj.context(|ctx| {
-        let content = fs::read_to_string("./data/init_world.lua").unwrap(); // load init func
-        let data = ctx.load(&content[..]).eval::<Table>().unwrap();
+        registry = MaterialRegistry::load(
+            ctx,
+            "./data/tags.lua",
+            "./data/materials.lua",
+            "./data/reactions.lua",
+        );
+        let file_seed;
+        (settings, max_catch_up, file_seed) =
+            sand::load_world_settings(ctx, "./data/world_settings.lua");
+        seed = arg_value(&args, "--seed")
+            .flatten()
+            .map(|seed| seed.parse().expect("seed must be a number"))
+            .or(file_seed)
+            .unwrap_or_else(|| {
+                SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap()
+                    .as_millis() as u32
+            });
+        println!("seed {seed}, run with --seed {seed} to replay this world");
+        sand::seed_lua(ctx, seed); // before any script gets to call math.random
+        let data: Table = lua_funcs::eval_script(ctx, "./data/init_world.lua"); // load init func
 
         for elem in data.pairs::<usize, Table>() {
             let (_, value) = elem.unwrap();
-            let mut building_mat = Material {
-                ..Default::default()
-            };
-            // let cv = value.get::<&str, u32>("id");
-            // if cv.is_ok() {
-            //     building_mat.id = cv.unwrap();
-            // }
-            handle_lua_elem!(u32, "id", id, value, building_mat);
-            handle_lua_elem!(f32, "mass", mass, value, building_mat);
-            handle_lua_elem!(f32, "force", force, value, building_mat);
-            handle_lua_elem!(f32, "stable", stable, value, building_mat);
-            handle_lua_elem!(u32, "tags", tags, value, building_mat);
-            handle_lua_elem!(u32, "gas", gas, value, building_mat);
-            handle_lua_vec!("colour", colour, 3, value, building_mat);
-            handle_lua_vec!("pos", pos, 2, value, building_mat);
-            handle_lua_vec!("vel", vel, 2, value, building_mat);
-            handle_lua_vec!("target", target, 2, value, building_mat);
-
-            world.push(Padded::<Material, 4>(building_mat))
+            let particle = lua_funcs::exit_on_error(registry.particle(&value));
+            world.push(Padded::<Material, PADDING>(particle))
         }
     });
 
-    let work_groups = [world.len() as u32 / 64u32, 1, 1]; // autocalc workgroups
-    let (
-        library,
-        physical_device,
-        _queue_family_index,
-        _instance,
-        device,
-        mut queues,
-        window,
-        surface,
-        event_loop,
-        window_size,
-    ) = gpu_constructor::construct_gpu();
-    // -=-=-=-=-=
-
-    let queue = queues.next().unwrap();
-
-    let memory_allocator: GenericMemoryAllocator<
-        std::sync::Arc<vulkano::memory::allocator::FreeListAllocator>,
-    > = StandardMemoryAllocator::new_default(device.clone());
+    let pool = ParticlePool::pad(&mut world); // fills the last workgroup with free slots
+    let terrain = Terrain::load("./data/terrain.png"); // optional, black is solid
 
     let mut entities = Vec::new(); // we need some garbage here so buffer init works, request lua to do it.
     lua_obj.context(|ctx| {
-        let content = fs::read_to_string("./data/init_entities.lua").unwrap(); // load init func
-        let data = ctx.load(&content[..]).eval::<Table>().unwrap();
+        let data: Table = lua_funcs::eval_script(ctx, "./data/init_entities.lua"); // load init func
 
         for elem in data.pairs::<usize, Table>() {
             let (_, value) = elem.unwrap();
@@ -146,6 +139,12 @@ fn main() {
                 handle_lua_vec!("vel", vel, 2, hitbox, entity.hitbox);
                 handle_lua_elem!(f32, "mass", mass, hitbox, entity.hitbox);
                 handle_lua_elem!(u32, "simulate", simulate, hitbox, entity.hitbox);
+                let shape = hitbox.get::<&str, Value>("shape").unwrap();
+                if !matches!(shape, Value::Nil) {
+                    entity.hitbox.shape = lua_funcs::exit_on_error(sand::shape_from_lua(&shape));
+                }
+                handle_lua_elem!(f32, "radius", radius, hitbox, entity.hitbox);
+                handle_lua_elem!(f32, "angle", angle, hitbox, entity.hitbox);
                 handle_lua_elem!(bool, "deleted", deleted, hitbox, entity);
             }
             handle_lua_elem!(String, "data", data, value, entity);
@@ -156,6 +155,36 @@ fn main() {
         // println!("{entities:?}");
     });
 
+    if let Some(ticks) = arg_value(&args, "--compare-backends") {
+        // headless, checks sand_particle.glsl against sand::cpu and exits
+        let ticks = ticks.map_or(1, |ticks| ticks.parse().expect("ticks must be a number"));
+        let hitboxes = entities.iter().map(|e| e.hitbox).collect();
+        let agreed = compare::run(
+            world, &pool, hitboxes, &registry, settings, &terrain, seed, ticks,
+        );
+        std::process::exit(if agreed { 0 } else { 1 });
+    }
+
+    let (
+        library,
+        physical_device,
+        _queue_family_index,
+        _instance,
+        device,
+        mut queues,
+        window,
+        surface,
+        event_loop,
+        window_size,
+    ) = gpu_constructor::construct_gpu();
+    // -=-=-=-=-=
+
+    let queue = queues.next().unwrap();
+
+    let memory_allocator: GenericMemoryAllocator<
+        std::sync::Arc<vulkano::memory::allocator::FreeListAllocator>,
+    > = StandardMemoryAllocator::new_default(device.clone());
+
     // let data2 = 0..64; //staging, gpu 1, gpu 2, download (eventually)
 
     // lua.context(|lua_ctx| {
@@ -176,7 +205,7 @@ fn main() {
         device,
         queue,
         world,
-        work_groups,
+        pool,
         physical_device,
         window,
         surface,
@@ -184,6 +213,22 @@ fn main() {
         window_size,
         entities,
         lua_obj,
+        registry,
+        settings,
+        terrain,
+        max_catch_up,
+        backend,
+        seed,
     );
     //main.rs is done now as window now has control
 }
+
+/// `Some(None)` for a flag given without a value, like `--compare-backends` on its own.
+fn arg_value<'a>(args: &'a [String], flag: &str) -> Option<Option<&'a str>> {
+    let at = args.iter().position(|arg| arg == flag)?;
+    Some(
+        args.get(at + 1)
+            .map(|value| &value[..])
+            .filter(|value| !value.starts_with("--")),
+    )
+}
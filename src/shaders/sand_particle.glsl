@@ -4,100 +4,609 @@
 
 struct Material {
 	vec3 colour; // 12
-	uint id; // 16 (unused) for binding forces
+	uint id; // 16 material type, index into types.mat
 	vec2 pos; // 24
 	vec2 vel; // 32
 	vec2 target; // 40 attractor point
 	float mass; // 44
-	float force; // 48 amount of attraction to target
-	float stable; // 52 amount of resistance to pushing before target decouples
-	uint tags; // 56 (unused) bit flags
-	uint gas; // 60 antigrav
-}; // +4
+	float force; // 48 amount of attraction to target, 0 is free and decoupled particles stay at 0
+	float stable; // 52 amount of resistance to pushing before target decouples (max distance from target)
+	uint tags; // 56 bit flags, names are in data/tags.lua
+	uint gas; // 60 gravity mode, see GAS_*
+	float temperature; // 64
+	uint alive; // 68 0 for free slots, see ParticlePool
+}; // +12
 
 struct Hitbox {
-	vec2 pos; // 8 (hitbox owns the real entity position so that buffers are better)
-	vec2 size; // 16
-	vec2 vel;
-	float mass; // 20
-	bool simulate; // 24 (booleans are not glbooleans and so are 32 bit alligned meaning 4 byte memory blocks)
-	bool deleted; // 28
-}; // +4
+	vec2 pos; // 8 (hitbox owns the real entity position so that buffers are better) top left of the bounding frame
+	vec2 size; // 16 every shape is centred in pos to pos + size
+	vec2 vel; // 24
+	float mass; // 28
+	bool simulate; // 32 (booleans are not glbooleans and so are 32 bit alligned meaning 4 byte memory blocks)
+	bool deleted; // 36
+	uint shape; // 40 see SHAPE_*
+	float radius; // 44 SHAPE_CIRCLE only
+	float angle; // 48 SHAPE_ROTATED only, radians clockwise on screen
+}; // +0
+
+struct MaterialType { // shared properties of every particle with the same id, see data/materials.lua
+	vec3 colour; // 12
+	float mass; // 16
+	float friction; // 20 how much its velocity is pulled towards the average of its neighbours, 0-1
+	float stable; // 24
+	uint gas; // 28
+	uint tags; // 32
+	float temperature; // 36 what particles of this material start at
+	float conductivity; // 40 0-1, how fast heat moves in and out
+	float melt_point; // 44 above this it turns into melt_into (melting, boiling)
+	uint melt_into; // 48 0 for never
+	float freeze_point; // 52 below this it turns into freeze_into (freezing, condensing, lava setting)
+	uint freeze_into; // 56 0 for never
+}; // +8
+
+struct Reaction { // see data/reactions.lua
+	uint tag; // 4 particles with any of these tags react
+	uint touching; // 8 when touching any of these tags, 0 means always
+	uint into; // 12 material id to turn into
+	float chance; // 16 per substep at BASE_RATE
+};
+
+struct GridCell {
+	uint count; // 4 particles in the cell, doubles as the write cursor while scattering
+	uint start; // 8 first slot of the cell in sorted.idx
+};
+
+// MUST BE KEPT IN SYNC WITH sand.rs
+const uint STAGE_CLEAR = 0; // zero every cell count
+const uint STAGE_COUNT = 1; // count particles per cell
+const uint STAGE_SCAN = 2; // prefix sum counts into start offsets, single work group
+const uint STAGE_SCATTER = 3; // write particle indices into their cells slots
+const uint STAGE_SORT = 4; // order each cell by particle index so sums don't depend on scheduling
+const uint STAGE_SIMULATE = 5; // the actual physics, only looks at neighbouring cells
+const uint STAGE_REDUCE = 6; // add the tick's accumulated impulses to entity velocities, once per tick
+
+const uint GAS_NORMAL = 0; // falls
+const uint GAS_ANTIGRAVITY = 1; // falls upwards, smoke
+const uint GAS_FLOATING = 2; // ignores gravity entirely
+const uint GAS_BUOYANT = 3; // sinks or rises depending on mass relative to its neighbours, bubbles
+
+const float BASE_RATE = 300.0; // substeps a second every constant was tuned at, scaled by settings.dt
+
+const uint BOUNDARY_WALL = 0; // clamped back in, bounces with settings.restitution
+const uint BOUNDARY_WRAP = 1; // leaves one side and comes back in the other
+const uint BOUNDARY_OPEN = 2; // leaves for good, the slot goes on the killed list to be reused
+
+const uint SHAPE_BOX = 0; // axis aligned, fills pos to pos + size
+const uint SHAPE_CIRCLE = 1; // radius around the centre of the frame
+const uint SHAPE_ROTATED = 2; // a size box turned by angle around the centre of the frame
+
+const uint GRID_SIZE = 50; // cells per axis, settings.size / GRID_SIZE must be at least settings.radius
+const uint GRID_CELLS = GRID_SIZE * GRID_SIZE;
+
+const float IMPULSE_SCALE = 4096.0; // fixed point steps per unit of velocity, integer sums don't depend on order
+const uint CONTACT_FIELDS = 3; // per entity and material: impulse x and y in IMPULSE_SCALE steps, grains that touched
 
 layout(local_size_x = 64, local_size_y = 1, local_size_z = 1) in;
 
-layout(binding = 0) buffer DataMaterial { // eventually need double buffer for non random results
+layout(binding = 0) readonly buffer DataMaterialIn { // last substep, swapped with buf every substep
+	Material mat[];
+}
+src;
+
+layout(binding = 1) writeonly buffer DataMaterial { // this substep
 	Material mat[];
 }
 buf;
 
-layout(binding = 1) buffer DataEntity { // eventually need double buffer for non random results
+layout(binding = 2) buffer DataEntity { // only STAGE_REDUCE writes, grains push through impulse
 	Hitbox ent[];
 }
 entity;
 
-float random (vec2 st) {
-    return fract(sin(dot(st.xy,vec2(12.9898,78.233)))*43758.5453123)*2.0-1.0; // https://thebookofshaders.com/10/
+layout(binding = 3) buffer DataGrid { // rebuilt every substep
+	GridCell cells[];
 }
+grid;
 
-void main() {
-	uint idx = gl_GlobalInvocationID.x;
-	float radius = 0.02;
-	buf.mat[idx].vel.y += 0.0005;
-	for(int i = 0; i < buf.mat.length(); i++)
+layout(binding = 4) buffer DataSorted { // particle indices ordered by cell
+	uint idx[];
+}
+sorted;
+
+layout(binding = 5) readonly buffer DataTypes { // material registry, indexed by Material.id
+	MaterialType mat[];
+}
+types;
+
+layout(binding = 6) readonly buffer DataReactions {
+	Reaction list[];
+}
+reactions;
+
+layout(binding = 7) uniform WorldSettings { // data/world_settings.lua, SetPhysics can change it at runtime
+	float gravity;
+	float radius; // particles closer than this push apart
+	float max_speed;
+	float damping; // velocity is multiplied by this every substep
+	float jitter; // random nudge size, helps edges
+	float wall_margin; // how close to the edge of the world before the walls push back
+	float wall_push;
+	float dt; // seconds simulated per substep
+	vec2 size; // the world runs from 0 to size on each axis
+	uint boundary_x; // BOUNDARY_*
+	uint boundary_y;
+	float restitution; // fraction of speed kept bouncing off a wall
+	float wall_margin_left; // the low x wall, grains always got ten times closer to that one. last so size stays 8 byte aligned
+}
+settings;
+
+layout(binding = 8) uniform ActiveCount { // shared with the fragment shader
+	uint count; // slots past here have never held a particle, see ParticlePool.next
+}
+active;
+
+layout(binding = 9) uniform Clock { // written by the host before every tick
+	uint seed; // --seed or data/world_settings.lua, same seed and inputs give the same world
+	uint tick; // ticks since startup
+}
+clock;
+
+layout(binding = 10) buffer DataKilled { // list[0] is how many follow, the host drains it every tick
+	uint list[];
+}
+killed;
+
+layout(binding = 11) uniform sampler2D terrain; // data/terrain.png, r is 1 where solid, stretched over the world
+
+layout(binding = 12) buffer DataImpulse { // x and y per entity in IMPULSE_SCALE steps, zeroed by STAGE_REDUCE
+	int acc[];
+}
+impulse;
+
+layout(binding = 13) buffer DataContacts { // CONTACT_FIELDS per material per entity, the host drains it every tick
+	int acc[];
+}
+contacts;
+
+layout(push_constant) uniform StagePush {
+	uint stage;
+	uint substep; // 0 or 1, which half of the tick
+}
+push;
+
+shared uint chunk_totals[64];
+
+uint pcg(uint v) { // https://www.jcgt.org/published/0009/03/02/
+	uint state = v * 747796405u + 2891336453u;
+	uint word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+	return (word >> 22u) ^ word;
+}
+
+uint seed_random(uint idx) {
+	// every particle gets its own stream each substep, nothing depends on scheduling or drivers' sin
+	return pcg(clock.seed ^ pcg((clock.tick * 2u + push.substep) ^ pcg(idx)));
+}
+
+float random(inout uint state) {
+	state = pcg(state);
+	return float(state >> 8u) / 16777216.0 * 2.0 - 1.0; // top 24 bits convert exactly, -1 to 1
+}
+
+ivec2 cell_of(vec2 pos) {
+	return clamp(ivec2(floor(pos / settings.size * float(GRID_SIZE))), ivec2(0), ivec2(GRID_SIZE - 1));
+}
+
+vec2 wrap_delta(vec2 d) {
+	// shortest way between two points, across the seam on wrapping axes
+	if (settings.boundary_x == BOUNDARY_WRAP)
+	{
+		d.x -= settings.size.x * floor(d.x / settings.size.x + 0.5);
+	}
+	if (settings.boundary_y == BOUNDARY_WRAP)
+	{
+		d.y -= settings.size.y * floor(d.y / settings.size.y + 0.5);
+	}
+	return d;
+}
+
+vec2 wrap_offset(vec2 d) {
+	// offset from a corner into 0 to size on wrapping axes, so boxes over the seam still contain things
+	if (settings.boundary_x == BOUNDARY_WRAP)
+	{
+		d.x = mod(d.x, settings.size.x);
+	}
+	if (settings.boundary_y == BOUNDARY_WRAP)
+	{
+		d.y = mod(d.y, settings.size.y);
+	}
+	return d;
+}
+
+bool apply_boundary(inout Material m, int axis, uint mode) {
+	// false when the particle left through an open side
+	float size = settings.size[axis];
+	if (mode == BOUNDARY_WRAP)
+	{
+		m.pos[axis] = mod(m.pos[axis], size);
+		return true;
+	}
+	if (m.pos[axis] < 0.0 || m.pos[axis] > size)
+	{
+		if (mode == BOUNDARY_OPEN)
+		{
+			return false;
+		}
+		m.pos[axis] = clamp(m.pos[axis], 0.0, size);
+		m.vel[axis] *= -settings.restitution;
+	}
+	if (mode == BOUNDARY_WALL)
+	{
+		float low_margin = axis == 0 ? settings.wall_margin_left : settings.wall_margin;
+		if (m.pos[axis] <= low_margin)
+		{
+			m.pos[axis] += settings.wall_push;
+		}
+		else if (m.pos[axis] >= size - settings.wall_margin)
+		{
+			m.pos[axis] -= settings.wall_push;
+		}
+	}
+	return true;
+}
+
+bool solid(vec2 pos) {
+	// texelFetch so the cpu backend reads exactly the same pixel, see Terrain::is_solid
+	ivec2 size = textureSize(terrain, 0);
+	ivec2 px = ivec2(floor(pos / settings.size * vec2(size)));
+	if (px.x < 0 || px.y < 0 || px.x >= size.x || px.y >= size.y)
+	{
+		return false;
+	}
+	return texelFetch(terrain, px, 0).r > 0.5;
+}
+
+void collide_terrain(inout Material m, vec2 from) {
+	// undo the move along whichever axis hit, so grains slide along slopes instead of sticking
+	if (!solid(m.pos) || solid(from))
+	{
+		return; // grains painted over fall out rather than staying stuck
+	}
+	if (!solid(vec2(m.pos.x, from.y)))
+	{
+		m.pos.y = from.y;
+		m.vel.y *= -settings.restitution;
+	}
+	else if (!solid(vec2(from.x, m.pos.y)))
+	{
+		m.pos.x = from.x;
+		m.vel.x *= -settings.restitution;
+	}
+	else
+	{
+		m.pos = from;
+		m.vel *= -settings.restitution;
+	}
+}
+
+vec2 rotate(vec2 v, float angle) {
+	float c = cos(angle);
+	float s = sin(angle);
+	return vec2(v.x * c - v.y * s, v.x * s + v.y * c);
+}
+
+bool hitbox_overlap(Hitbox h, vec2 pos, out vec2 local, out float depth) {
+	// local points the way the grain gets pushed out, away from the centre or the nearest face for rotated boxes
+	// depth is how far in the grain is
+	vec2 centre = h.pos + h.size / 2.0;
+	if (h.shape == SHAPE_CIRCLE)
+	{
+		local = wrap_delta(pos - centre);
+		depth = h.radius - length(local);
+		return depth > 0.0;
+	}
+	if (h.shape == SHAPE_ROTATED)
+	{
+		vec2 along = rotate(wrap_delta(pos - centre), -h.angle); // into the box's own axes
+		vec2 inset = h.size / 2.0 - abs(along); // distance to each pair of faces
+		// out through the nearest face, so a grain in the tip of a plank is pushed as hard as one in the middle
+		vec2 normal = inset.x < inset.y ? vec2(along.x < 0.0 ? -1.0 : 1.0, 0.0) : vec2(0.0, along.y < 0.0 ? -1.0 : 1.0);
+		depth = min(inset.x, inset.y);
+		local = rotate(normal, h.angle);
+		return depth > 0.0;
+	}
+	local = wrap_offset(pos - h.pos);
+	bool inside = (local.x < h.size.x) && (local.y < h.size.y) && (local.x > 0.0) && (local.y > 0.0); // bounding check
+	local -= h.size / 2.0;
+	depth = length(h.size / 2.0) - abs(length(local));
+	return inside;
+}
+
+uint cell_index(vec2 pos) {
+	ivec2 cell = cell_of(pos);
+	return uint(cell.y) * GRID_SIZE + uint(cell.x);
+}
+
+void turn_into(inout Material m, uint id) {
+	// keeps position, velocity and temperature, everything else comes from the new material
+	// which is free, a burnt wall falls instead of staying pulled to where the wall was
+	MaterialType kind = types.mat[id];
+	m.id = id;
+	m.target = m.pos;
+	m.force = 0.0;
+	m.colour = kind.colour;
+	m.mass = kind.mass;
+	m.stable = kind.stable;
+	m.gas = kind.gas;
+	m.tags = kind.tags;
+}
+
+void simulate(uint idx) {
+	Material m = src.mat[idx]; // everything is read from src so the order invocations run in doesn't matter
+	if (m.alive == 0u)
+	{
+		buf.mat[idx] = m; // free slots still need carrying across to the other buffer
+		return;
+	}
+	uint rng = seed_random(idx);
+	float ts = settings.dt * BASE_RATE; // 1.0 at the rate the constants were tuned for
+	float radius = settings.radius;
+	float gravity = settings.gravity * ts;
+	float neighbour_mass = 0.0;
+	vec2 neighbour_vel = vec2(0.0);
+	float neighbours = 0.0;
+	uint touching = 0u; // every tag we are touching
+	float heat = 0.0; // conducted in from neighbours
+	float conductivity = m.id < uint(types.mat.length()) ? types.mat[m.id].conductivity : 0.0;
+	ivec2 home = cell_of(m.pos);
+	for(int dy = -1; dy <= 1; dy++)
+	{
+		for(int dx = -1; dx <= 1; dx++)
+		{
+			ivec2 cell = home + ivec2(dx, dy);
+			if (settings.boundary_x == BOUNDARY_WRAP)
+			{
+				cell.x = (cell.x + int(GRID_SIZE)) % int(GRID_SIZE);
+			}
+			if (settings.boundary_y == BOUNDARY_WRAP)
+			{
+				cell.y = (cell.y + int(GRID_SIZE)) % int(GRID_SIZE);
+			}
+			if (cell.x < 0 || cell.y < 0 || cell.x >= int(GRID_SIZE) || cell.y >= int(GRID_SIZE))
+			{
+				continue;
+			}
+			uint c = uint(cell.y) * GRID_SIZE + uint(cell.x);
+			uint end = grid.cells[c].start + grid.cells[c].count;
+			for(uint s = grid.cells[c].start; s < end; s++)
+			{
+				uint i = sorted.idx[s];
+				vec2 dir = wrap_delta(m.pos-src.mat[i].pos);
+				float size = length(dir); 
+				if (size < radius && i != idx) // diameter
+				{
+					m.vel += pow((radius-size)*(1.0/radius),0.5)*dir*ts;
+					// m.pos += dir/4.0;
+					neighbour_mass += src.mat[i].mass;
+					neighbour_vel += src.mat[i].vel;
+					neighbours += 1.0;
+					touching |= src.mat[i].tags;
+					float other_conductivity = src.mat[i].id < uint(types.mat.length()) ? types.mat[src.mat[i].id].conductivity : 0.0;
+					heat += (src.mat[i].temperature - m.temperature) * (conductivity + other_conductivity) * 0.5;
+				}
+			}
+		}
+	}
+
+	if (neighbours > 0.0 && m.id < uint(types.mat.length()))
+	{
+		m.vel = mix(m.vel, neighbour_vel / neighbours, min(types.mat[m.id].friction * ts, 1.0));
+	}
+
+	for(int r = 0; r < reactions.list.length(); r++)
+	{
+		Reaction reaction = reactions.list[r];
+		if ((m.tags & reaction.tag) == 0u || (reaction.touching != 0u && (touching & reaction.touching) == 0u))
+		{
+			continue;
+		}
+		if (random(rng) * 0.5 + 0.5 < reaction.chance * ts)
+		{
+			turn_into(m, reaction.into);
+			break; // one reaction a substep is plenty
+		}
+	}
+
+	if (m.id < uint(types.mat.length()))
 	{
-		vec2 dir = buf.mat[idx].pos-buf.mat[i].pos;
-		float size = length(dir); 
-		if (size < radius && i != idx) // diameter
+		if (neighbours > 0.0)
+		{
+			m.temperature += heat / neighbours * min(ts, 1.0);
+		}
+		MaterialType kind = types.mat[m.id];
+		if (kind.melt_into != 0u && m.temperature > kind.melt_point)
+		{
+			turn_into(m, kind.melt_into);
+		}
+		else if (kind.freeze_into != 0u && m.temperature < kind.freeze_point)
 		{
-			buf.mat[idx].vel += pow((radius-size)*(1.0/radius),0.5)*dir;
-			// buf.mat[idx].pos += dir/4.0;
+			turn_into(m, kind.freeze_into);
 		}
 	}
 
+	if (m.gas == GAS_NORMAL)
+	{
+		m.vel.y += gravity;
+	}
+	else if (m.gas == GAS_ANTIGRAVITY)
+	{
+		m.vel.y -= gravity;
+	}
+	else if (m.gas == GAS_BUOYANT && neighbours > 0.0) // nothing to displace means it just drifts
+	{
+		float surrounding = neighbour_mass / neighbours;
+		m.vel.y += gravity * clamp((m.mass - surrounding) / max(surrounding, 0.0001), -1.0, 1.0);
+	}
+
 	for(int i = 0; i < entity.ent.length(); i++)
 	{
 		if (!entity.ent[i].simulate || entity.ent[i].deleted)
 		{
 			continue;
 		}
-		vec2 local = buf.mat[idx].pos - entity.ent[i].pos;
-		if ((local.x < entity.ent[i].size.x) && (local.y < entity.ent[i].size.y) && (local.x > 0.0) && (local.y > 0.0))  // bounding check
+		vec2 local;
+		float mag;
+		if (hitbox_overlap(entity.ent[i], m.pos, local, mag))
 		{
-			local -= entity.ent[i].size / 2.0;
-			float mag = length(entity.ent[i].size / 2.0) - abs(length(local));
-			entity.ent[i].vel -= normalize(local) * mag / entity.ent[i].mass;
-			buf.mat[idx].vel += normalize(local) * mag / buf.mat[idx].mass;
+			vec2 push_back = -normalize(local) * mag / entity.ent[i].mass * ts;
+			ivec2 fixed_push = ivec2(floor(push_back * IMPULSE_SCALE + 0.5));
+			atomicAdd(impulse.acc[i * 2], fixed_push.x);
+			atomicAdd(impulse.acc[i * 2 + 1], fixed_push.y);
+			// totals per material rather than a list so what scripts see doesn't depend on thread order
+			uint materials = uint(types.mat.length());
+			uint slot = (uint(i) * materials + (m.id < materials ? m.id : 0u)) * CONTACT_FIELDS;
+			atomicAdd(contacts.acc[slot], fixed_push.x);
+			atomicAdd(contacts.acc[slot + 1], fixed_push.y);
+			atomicAdd(contacts.acc[slot + 2], 1);
+			m.vel += normalize(local) * mag / m.mass * ts;
 		}
 	}
 
-	buf.mat[idx].vel += vec2(random(buf.mat[idx].vel+buf.mat[idx].pos),random(buf.mat[idx].pos*2.0-buf.mat[idx].vel))/10000.0; // helps edges
-	buf.mat[idx].pos += buf.mat[idx].vel/100.0;
-	buf.mat[idx].pos.x = min(1.0,max(buf.mat[idx].pos.x,0.0));
-	// buf.mat[idx].pos.x = mod(buf.mat[idx].pos.x,1.0);
-	// buf.mat[idx].pos = vec2(idx/64.0);
-	buf.mat[idx].pos.y = min(1.0,max(buf.mat[idx].pos.y,0.0));
-	if (buf.mat[idx].pos.x <= 0.0005)
+	if (m.force > 0.0) // static "solid", held in place by a spring to its target
 	{
-		buf.mat[idx].pos.x += 0.0006;
+		vec2 offset = wrap_delta(m.target - m.pos);
+		if (length(offset) > m.stable)
+		{
+			m.force = 0.0; // pushed too far, breaks off for good
+		}
+		else
+		{
+			m.vel += offset * m.force * ts;
+			m.vel *= pow(0.5, ts); // damp hard or walls wobble forever
+		}
 	}
-	else if (buf.mat[idx].pos.x >= 0.995)
+
+	float jitter_x = random(rng);
+	m.vel += vec2(jitter_x, random(rng))*settings.jitter*ts; // helps edges
+	vec2 from = m.pos;
+	m.pos += m.vel/100.0*ts;
+	collide_terrain(m, from);
+	if (!apply_boundary(m, 0, settings.boundary_x) || !apply_boundary(m, 1, settings.boundary_y))
+	{
+		m.alive = 0u;
+		uint slot = atomicAdd(killed.list[0], 1u) + 1u;
+		if (slot < uint(killed.list.length()))
+		{
+			killed.list[slot] = idx;
+		}
+		buf.mat[idx] = m;
+		return;
+	}
+	if(length(m.vel)>settings.max_speed)
 	{
-		buf.mat[idx].pos.x -= 0.0006;
+		m.vel = m.vel / length(m.vel) * settings.max_speed;
+	}
+	m.vel *= pow(settings.damping, ts);
+	buf.mat[idx] = m;
+}
+
+void sort_cell(uint c) {
+	// insertion sort, cells only ever hold a handful of particles
+	uint start = grid.cells[c].start;
+	uint end = start + grid.cells[c].count;
+	for(uint s = start + 1; s < end; s++)
+	{
+		uint value = sorted.idx[s];
+		uint t = s;
+		while (t > start && sorted.idx[t - 1] > value)
+		{
+			sorted.idx[t] = sorted.idx[t - 1];
+			t--;
+		}
+		sorted.idx[t] = value;
 	}
+}
 
-	if (buf.mat[idx].pos.y <= 0.005)
+void scan_cells() {
+	// each lane prefix sums its own run of cells, lane 0 then offsets the runs
+	uint lane = gl_LocalInvocationID.x;
+	uint chunk = (GRID_CELLS + 63) / 64;
+	uint begin = min(lane * chunk, GRID_CELLS);
+	uint end = min(begin + chunk, GRID_CELLS);
+	uint total = 0;
+	for(uint c = begin; c < end; c++)
 	{
-		buf.mat[idx].pos.y += 0.0006;
+		total += grid.cells[c].count;
 	}
-	else if (buf.mat[idx].pos.y >= 0.995)
+	chunk_totals[lane] = total;
+	memoryBarrierShared();
+	barrier();
+	if (lane == 0)
 	{
-		buf.mat[idx].pos.y -= 0.0006;
+		uint running = 0;
+		for(uint i = 0; i < 64; i++)
+		{
+			uint t = chunk_totals[i];
+			chunk_totals[i] = running;
+			running += t;
+		}
 	}
-	float max_speed = 0.1;
-	if(length(buf.mat[idx].vel)>max_speed)
+	memoryBarrierShared();
+	barrier();
+	uint running = chunk_totals[lane];
+	for(uint c = begin; c < end; c++)
 	{
-		buf.mat[idx].vel = buf.mat[idx].vel / length(buf.mat[idx].vel) * max_speed;
+		grid.cells[c].start = running;
+		running += grid.cells[c].count;
+		grid.cells[c].count = 0; // scatter counts back up from 0
+	}
+}
+
+void main() {
+	uint idx = gl_GlobalInvocationID.x;
+	if (push.stage == STAGE_CLEAR)
+	{
+		if (idx < GRID_CELLS)
+		{
+			grid.cells[idx].count = 0;
+		}
 	}
-	buf.mat[idx].vel *= 0.999;
-}
\ No newline at end of file
+	else if (push.stage == STAGE_COUNT)
+	{
+		if (idx < active.count && src.mat[idx].alive != 0u)
+		{
+			atomicAdd(grid.cells[cell_index(src.mat[idx].pos)].count, 1u);
+		}
+	}
+	else if (push.stage == STAGE_SCAN)
+	{
+		scan_cells();
+	}
+	else if (push.stage == STAGE_SCATTER)
+	{
+		if (idx < active.count && src.mat[idx].alive != 0u)
+		{
+			uint c = cell_index(src.mat[idx].pos);
+			sorted.idx[grid.cells[c].start + atomicAdd(grid.cells[c].count, 1u)] = idx;
+		}
+	}
+	else if (push.stage == STAGE_SORT)
+	{
+		if (idx < GRID_CELLS)
+		{
+			sort_cell(idx);
+		}
+	}
+	else if (push.stage == STAGE_REDUCE)
+	{
+		if (idx < uint(entity.ent.length()))
+		{
+			entity.ent[idx].vel += vec2(impulse.acc[idx * 2], impulse.acc[idx * 2 + 1]) / IMPULSE_SCALE;
+			impulse.acc[idx * 2] = 0;
+			impulse.acc[idx * 2 + 1] = 0;
+		}
+	}
+	else if (idx < active.count) // the last workgroup is usually only partly full
+	{
+		simulate(idx);
+	}
+}
@@ -11,7 +11,9 @@ struct Material {
 	float stable; // 52
 	uint tags; // 56
 	uint gas; // 60
-}; // +4
+	float temperature; // 64
+	uint alive; // 68
+}; // +12
 
 struct Sprite {
 	vec2 pos; // 8
@@ -33,6 +35,30 @@ sprite_buf;
 
 layout(set = 0, binding = 2) uniform sampler2D atlas;
 
+layout(set = 0, binding = 3) uniform ActiveCount {
+	uint count; // the world buffer is bigger than this, the rest are free slots
+}
+active;
+
+layout(set = 0, binding = 4) uniform WorldSettings { // MUST BE KEPT IN SYNC WITH sand_particle.glsl
+	float gravity;
+	float radius;
+	float max_speed;
+	float damping;
+	float jitter;
+	float wall_margin;
+	float wall_push;
+	float dt;
+	vec2 size;
+	uint boundary_x;
+	uint boundary_y;
+	float restitution;
+	float wall_margin_left;
+}
+settings;
+
+layout(set = 0, binding = 5) uniform sampler2D terrain; // same image the compute shader collides with
+
 layout( push_constant ) uniform PushType
 {
 	vec2 dims;
@@ -42,12 +68,17 @@ layout(location = 0) out vec4 f_color;
 
 void main() {
 	Sprite _1 = sprite_buf.sprites[0];
-	float radius = 0.02/2.0*1.2; // coeff to hide bg
+	float radius = settings.radius/2.0*1.2; // coeff to hide bg
 	vec2 uv = gl_FragCoord.xy / PushConstants.dims;
+	vec2 world = uv * settings.size; // the whole world is stretched over the window
 	vec3 c = vec3(0.4,0.45,1.0);
-	for(int i = 0; i < buf.mat.length(); i++)
+	if (texture(terrain, uv).r > 0.5) // nearest, so the edges match what grains collide with
+	{
+		c = vec3(0.3,0.27,0.25);
+	}
+	for(uint i = 0u; i < active.count; i++)
 	{
-		if (length(buf.mat[i].pos-uv) < radius)
+		if (buf.mat[i].alive != 0u && length(buf.mat[i].pos-world) < radius)
 		{
 			c = buf.mat[i].colour;
 			break; // + ~10% fps
@@ -1,11 +1,17 @@
 use std::default;
+use std::sync::Mutex;
 
+use glam::Vec2;
 use rlua::Value::Nil;
-use rlua::{Context, Function, Table};
+use rlua::{Context, FromLua, Function, Table, Value};
 use vulkano::padded::Padded;
 
-use crate::lua_funcs;
-use crate::simulation::sand::sand_shader::Hitbox;
+use crate::lua_funcs::{self, Controls, FrameInfo};
+use crate::simulation::sand::sand_shader::{Hitbox, WorldSettings};
+use crate::simulation::sand::{
+    self, Contact, BASE_RATE, BOUNDARY_WALL, BOUNDARY_WRAP, HITBOX_PADDING, SHAPE_CIRCLE,
+    SHAPE_ROTATED,
+};
 use crate::window::init::fragment_shader::Sprite;
 use vulkano::buffer::Subbuffer;
 
@@ -32,16 +38,350 @@ impl Default for Entity {
     }
 }
 
-fn regen_from_gpu(entities: &mut Vec<Entity>, buffer: &Subbuffer<[Padded<Hitbox, 4>]>) {
+/// One field of an entity, what EntitySetComponentValue and EntityGetComponentValue paths name.
+#[derive(Clone, Debug)]
+pub enum Component {
+    SpritePos([f32; 2]),
+    SpriteSize([f32; 2]),
+    SpriteOffset([f32; 2]),
+    SpriteScale([f32; 2]),
+    HitboxPos([f32; 2]),
+    HitboxSize([f32; 2]),
+    HitboxVel([f32; 2]),
+    HitboxMass(f32),
+    HitboxSimulate(bool),
+    HitboxShape(u32),
+    HitboxRadius(f32),
+    HitboxAngle(f32),
+    Data(String),
+    Deleted(bool),
+}
+
+impl Component {
+    /// `values` is the table scripts pass, `{ x, y }` for vectors and `{ value }` for the rest.
+    pub fn parse(path: &str, values: &Table) -> rlua::Result<Component> {
+        let vec2 = || -> rlua::Result<[f32; 2]> { Ok([values.get(1)?, values.get(2)?]) };
+        Ok(match path {
+            "sprite.pos" => Component::SpritePos(vec2()?),
+            "sprite.size" => Component::SpriteSize(vec2()?),
+            "sprite.offset" => Component::SpriteOffset(vec2()?),
+            "sprite.scale" => Component::SpriteScale(vec2()?),
+            "hitbox.pos" => Component::HitboxPos(vec2()?),
+            "hitbox.size" => Component::HitboxSize(vec2()?),
+            "hitbox.vel" => Component::HitboxVel(vec2()?),
+            "hitbox.mass" => Component::HitboxMass(values.get(1)?),
+            "hitbox.simulate" => Component::HitboxSimulate(values.get(1)?),
+            "hitbox.shape" => Component::HitboxShape(sand::shape_from_lua(&values.get(1)?)?),
+            "hitbox.radius" => Component::HitboxRadius(values.get(1)?),
+            "hitbox.angle" => Component::HitboxAngle(values.get(1)?),
+            "data" => Component::Data(values.get(1)?),
+            "deleted" => Component::Deleted(values.get(1)?),
+            _ => return Err(rlua::Error::RuntimeError(format!("invalid path {path}"))),
+        })
+    }
+
+    /// The current value of `path`, None for paths parse wouldn't accept either.
+    pub fn read(entity: &Entity, path: &str) -> Option<Component> {
+        Some(match path {
+            "sprite.pos" => Component::SpritePos(entity.sprite.pos),
+            "sprite.size" => Component::SpriteSize(entity.sprite.size),
+            "sprite.offset" => Component::SpriteOffset(entity.sprite.offset),
+            "sprite.scale" => Component::SpriteScale(entity.sprite.scale),
+            "hitbox.pos" => Component::HitboxPos(entity.hitbox.pos),
+            "hitbox.size" => Component::HitboxSize(entity.hitbox.size),
+            "hitbox.vel" => Component::HitboxVel(entity.hitbox.vel),
+            "hitbox.mass" => Component::HitboxMass(entity.hitbox.mass),
+            "hitbox.simulate" => Component::HitboxSimulate(entity.hitbox.simulate == 1),
+            "hitbox.shape" => Component::HitboxShape(entity.hitbox.shape),
+            "hitbox.radius" => Component::HitboxRadius(entity.hitbox.radius),
+            "hitbox.angle" => Component::HitboxAngle(entity.hitbox.angle),
+            "data" => Component::Data(entity.data.clone()),
+            "deleted" => Component::Deleted(entity.deleted),
+            _ => return None,
+        })
+    }
+
+    /// Same names parse takes, so queued edits can be matched against reads.
+    pub fn path(&self) -> &'static str {
+        match self {
+            Component::SpritePos(_) => "sprite.pos",
+            Component::SpriteSize(_) => "sprite.size",
+            Component::SpriteOffset(_) => "sprite.offset",
+            Component::SpriteScale(_) => "sprite.scale",
+            Component::HitboxPos(_) => "hitbox.pos",
+            Component::HitboxSize(_) => "hitbox.size",
+            Component::HitboxVel(_) => "hitbox.vel",
+            Component::HitboxMass(_) => "hitbox.mass",
+            Component::HitboxSimulate(_) => "hitbox.simulate",
+            Component::HitboxShape(_) => "hitbox.shape",
+            Component::HitboxRadius(_) => "hitbox.radius",
+            Component::HitboxAngle(_) => "hitbox.angle",
+            Component::Data(_) => "data",
+            Component::Deleted(_) => "deleted",
+        }
+    }
+
+    /// Vector components, the ones scripts can index with `.x` and `.y`.
+    pub fn vec2(&self) -> Option<[f32; 2]> {
+        match *self {
+            Component::SpritePos(v)
+            | Component::SpriteSize(v)
+            | Component::SpriteOffset(v)
+            | Component::SpriteScale(v)
+            | Component::HitboxPos(v)
+            | Component::HitboxSize(v)
+            | Component::HitboxVel(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// The same component holding `value` instead, vectors take `{ x = 1, y = 2 }` or `{ 1, 2 }`.
+    pub fn set<'lua>(self, lua_ctx: Context<'lua>, value: Value<'lua>) -> rlua::Result<Component> {
+        if self.vec2().is_some() {
+            let table = Table::from_lua(value, lua_ctx)?;
+            let x = table.get("x").or_else(|_| table.get(1))?;
+            let y = table.get("y").or_else(|_| table.get(2))?;
+            return Ok(self.with_vec2([x, y]));
+        }
+        Ok(match self {
+            Component::HitboxMass(_) => Component::HitboxMass(f32::from_lua(value, lua_ctx)?),
+            Component::HitboxSimulate(_) => {
+                Component::HitboxSimulate(bool::from_lua(value, lua_ctx)?)
+            }
+            Component::HitboxShape(_) => Component::HitboxShape(sand::shape_from_lua(&value)?),
+            Component::HitboxRadius(_) => Component::HitboxRadius(f32::from_lua(value, lua_ctx)?),
+            Component::HitboxAngle(_) => Component::HitboxAngle(f32::from_lua(value, lua_ctx)?),
+            Component::Data(_) => Component::Data(String::from_lua(value, lua_ctx)?),
+            Component::Deleted(_) => Component::Deleted(bool::from_lua(value, lua_ctx)?),
+            vector => vector, // handled above
+        })
+    }
+
+    /// Replaces a vector component's value, anything else is returned unchanged.
+    pub fn with_vec2(self, v: [f32; 2]) -> Component {
+        match self {
+            Component::SpritePos(_) => Component::SpritePos(v),
+            Component::SpriteSize(_) => Component::SpriteSize(v),
+            Component::SpriteOffset(_) => Component::SpriteOffset(v),
+            Component::SpriteScale(_) => Component::SpriteScale(v),
+            Component::HitboxPos(_) => Component::HitboxPos(v),
+            Component::HitboxSize(_) => Component::HitboxSize(v),
+            Component::HitboxVel(_) => Component::HitboxVel(v),
+            other => other,
+        }
+    }
+
+    pub fn apply(self, entity: &mut Entity) {
+        match self {
+            Component::SpritePos(pos) => entity.sprite.pos = pos,
+            Component::SpriteSize(size) => entity.sprite.size = size,
+            Component::SpriteOffset(offset) => entity.sprite.offset = offset,
+            Component::SpriteScale(scale) => entity.sprite.scale = scale,
+            Component::HitboxPos(pos) => entity.hitbox.pos = pos,
+            Component::HitboxSize(size) => entity.hitbox.size = size,
+            Component::HitboxVel(vel) => entity.hitbox.vel = vel,
+            Component::HitboxMass(mass) => entity.hitbox.mass = mass,
+            Component::HitboxSimulate(simulate) => entity.hitbox.simulate = simulate as u32,
+            Component::HitboxShape(shape) => entity.hitbox.shape = shape,
+            Component::HitboxRadius(radius) => entity.hitbox.radius = radius,
+            Component::HitboxAngle(angle) => entity.hitbox.angle = angle,
+            Component::Data(data) => entity.data = data,
+            Component::Deleted(deleted) => {
+                // can't pop because then eid changes
+                entity.deleted = deleted;
+                entity.hitbox.deleted = deleted as u32;
+                entity.sprite.deleted = deleted as u32;
+            }
+        }
+    }
+}
+
+/// Edits scripts queue on Controls, regenerate applies them in order once tick.lua returns.
+#[derive(Clone, Debug)]
+pub enum Command {
+    Set(usize, Component),
+    Create(usize),
+}
+
+/// Something touching an entity this frame, grains come from the compute pass and boxes from
+/// step_physics. Lua gets these through OnCollision and GetCollisions.
+#[derive(Clone, Debug)]
+pub struct Collision {
+    pub entity: usize,
+    pub material: Option<u32>, // the grains' Material.id
+    pub other: Option<usize>,  // the other entity for box on box
+    pub impulse: [f32; 2],     // total push the entity got
+}
+
+/// One record per entity and material or entity pair, several ticks can run in a frame.
+fn merge(collisions: &mut Vec<Collision>, collision: Collision) {
+    let same = collisions.iter_mut().find(|c| {
+        c.entity == collision.entity
+            && c.material == collision.material
+            && c.other == collision.other
+    });
+    match same {
+        Some(c) => {
+            c.impulse[0] += collision.impulse[0];
+            c.impulse[1] += collision.impulse[1];
+        }
+        None => collisions.push(collision),
+    }
+}
+
+fn regen_from_gpu(
+    entities: &mut Vec<Entity>,
+    buffer: &Subbuffer<[Padded<Hitbox, HITBOX_PADDING>]>,
+) {
     for (key, value) in buffer.read().unwrap().into_iter().enumerate() {
         entities[key].hitbox = **value;
     }
 }
 
+/// Moves every simulated hitbox by the pushes sand left in vel on the gpu, gravity and each other.
+/// Steps once per substep the world moved this frame so entities keep pace with the grains.
+fn step_physics(
+    entities: &mut [Entity],
+    settings: &WorldSettings,
+    delta: f64,
+    collisions: &mut Vec<Collision>,
+) {
+    if settings.dt <= 0.0 {
+        return;
+    }
+    let steps = (delta / settings.dt as f64).round() as u32;
+    let ts = settings.dt * BASE_RATE;
+    for _ in 0..steps {
+        for entity in entities.iter_mut() {
+            if entity.hitbox.simulate != 0 && entity.hitbox.deleted == 0 {
+                integrate(&mut entity.hitbox, settings, ts);
+            }
+        }
+        for b in 1..entities.len() {
+            let (before, after) = entities.split_at_mut(b);
+            for (a, first) in before.iter_mut().enumerate() {
+                let Some((gained_a, gained_b)) = resolve_contact(
+                    &mut first.hitbox,
+                    &mut after[0].hitbox,
+                    settings.restitution,
+                ) else {
+                    continue;
+                };
+                merge(
+                    collisions,
+                    Collision {
+                        entity: a,
+                        material: None,
+                        other: Some(b),
+                        impulse: gained_a,
+                    },
+                );
+                merge(
+                    collisions,
+                    Collision {
+                        entity: b,
+                        material: None,
+                        other: Some(a),
+                        impulse: gained_b,
+                    },
+                );
+            }
+        }
+    }
+}
+
+/// Same units as grains, see simulate() in sand_particle.glsl.
+fn integrate(hitbox: &mut Hitbox, settings: &WorldSettings, ts: f32) {
+    let mut vel = Vec2::from(hitbox.vel);
+    vel.y += settings.gravity * hitbox.mass * ts; // heavier boxes fall faster, mass 0 floats
+    if vel.length() > settings.max_speed {
+        vel = vel / vel.length() * settings.max_speed;
+    }
+    vel *= settings.damping.powf(ts);
+    let mut pos = Vec2::from(hitbox.pos) + vel / 100.0 * ts;
+    let modes = [settings.boundary_x, settings.boundary_y];
+    let half = half_extent(hitbox);
+    for (axis, mode) in modes.into_iter().enumerate() {
+        let inset = hitbox.size[axis] / 2.0 - half[axis]; // from pos to the shape's near edge
+        let (low, high) = (-inset, settings.size[axis] - hitbox.size[axis] + inset);
+        if mode == BOUNDARY_WRAP {
+            pos[axis] = pos[axis].rem_euclid(settings.size[axis]);
+        } else if mode == BOUNDARY_WALL && (pos[axis] < low || pos[axis] > high) {
+            pos[axis] = pos[axis].clamp(low, high.max(low));
+            vel[axis] *= -settings.restitution;
+        } // open sides let boxes fall out of the world
+    }
+    hitbox.pos = pos.into();
+    hitbox.vel = vel.into();
+}
+
+/// Half the axis aligned box around the shape, entities only collide with each other as boxes.
+fn half_extent(hitbox: &Hitbox) -> Vec2 {
+    let half = Vec2::from(hitbox.size) / 2.0;
+    if hitbox.shape == SHAPE_CIRCLE {
+        Vec2::splat(hitbox.radius)
+    } else if hitbox.shape == SHAPE_ROTATED {
+        let (sin, cos) = hitbox.angle.sin_cos();
+        Vec2::new(
+            half.x * cos.abs() + half.y * sin.abs(),
+            half.x * sin.abs() + half.y * cos.abs(),
+        )
+    } else {
+        half
+    }
+}
+
+/// Non simulated boxes are immovable, everything else splits the push by mass.
+fn inverse_mass(hitbox: &Hitbox) -> f32 {
+    if hitbox.simulate == 0 || hitbox.mass <= 0.0 {
+        0.0
+    } else {
+        1.0 / hitbox.mass
+    }
+}
+
+/// Pushes two overlapping boxes apart along the shallower axis and stops them closing,
+/// returns the velocity a and b each gained when they touched.
+fn resolve_contact(
+    a: &mut Hitbox,
+    b: &mut Hitbox,
+    restitution: f32,
+) -> Option<([f32; 2], [f32; 2])> {
+    if a.deleted != 0 || b.deleted != 0 {
+        return None;
+    }
+    let (inv_a, inv_b) = (inverse_mass(a), inverse_mass(b));
+    let total = inv_a + inv_b;
+    if total == 0.0 {
+        return None; // two fixed boxes
+    }
+    let (half_a, half_b) = (half_extent(a), half_extent(b));
+    let between = (Vec2::from(b.pos) + Vec2::from(b.size) / 2.0)
+        - (Vec2::from(a.pos) + Vec2::from(a.size) / 2.0); // centre to centre
+    let overlap = half_a + half_b - between.abs();
+    if overlap.x <= 0.0 || overlap.y <= 0.0 {
+        return None;
+    }
+    let axis = if overlap.x < overlap.y { 0 } else { 1 };
+    let sign = if between[axis] >= 0.0 { 1.0 } else { -1.0 };
+    a.pos[axis] -= sign * overlap[axis] * inv_a / total;
+    b.pos[axis] += sign * overlap[axis] * inv_b / total;
+    let closing = (a.vel[axis] - b.vel[axis]) * sign;
+    let (mut gained_a, mut gained_b) = ([0f32; 2], [0f32; 2]);
+    if closing > 0.0 {
+        let impulse = closing * (1.0 + restitution) / total;
+        gained_a[axis] = -sign * impulse * inv_a; // zero for a pinned box, it didn't move
+        gained_b[axis] = sign * impulse * inv_b;
+        a.vel[axis] += gained_a[axis];
+        b.vel[axis] += gained_b[axis];
+    }
+    Some((gained_a, gained_b))
+}
+
 fn regen_from_cpu(
     entities: &Vec<Entity>,
     sprite_buffer: &mut Subbuffer<[Padded<Sprite, 4>]>,
-    hitbox_buffer: &mut Subbuffer<[Padded<Hitbox, 4>]>,
+    hitbox_buffer: &mut Subbuffer<[Padded<Hitbox, HITBOX_PADDING>]>,
 ) {
     let mut buffer_writer_sprite = sprite_buffer.write().unwrap(); // locks
     let mut buffer_writer_hitbox = hitbox_buffer.write().unwrap();
@@ -56,98 +396,88 @@ fn regen_from_cpu(
     }
 }
 
+/// `entities` is the storage the lua bindings share, it's never locked while a script runs.
+/// A script error skips that frame's edits, entity or otherwise, the world keeps going and the
+/// caller reports it.
 pub fn regenerate(
-    entities: &mut Vec<Entity>,
+    entities: &Mutex<Vec<Entity>>,
     sprite_buffer: &mut Subbuffer<[Padded<Sprite, 4>]>,
-    hitbox_buffer: &mut Subbuffer<[Padded<Hitbox, 4>]>,
+    hitbox_buffer: &mut Subbuffer<[Padded<Hitbox, HITBOX_PADDING>]>,
     ctx: Context,
+    frame_info: &Mutex<FrameInfo>,
     frame: usize,
     time: u128,
-) {
-    regen_from_gpu(entities, hitbox_buffer); // gpu can only write to hitboxes
-    lua_funcs::create(ctx, entities.clone(), frame, time); // rust safety requires this massive performance hit and general difficulty causer
-    ctx.globals()
-        .set("RS_deltas", ctx.create_table().unwrap())
-        .unwrap(); // don't leak memory
-    ctx.globals()
-        .set("RS_created", ctx.create_table().unwrap())
-        .unwrap(); // don't leak memory
-    ctx.load("RS_tick_handle()").exec().unwrap();
+    delta: f64,
+    contacts: &[Contact],
+    controls: &Mutex<Controls>,
+) -> rlua::Result<()> {
+    let mut locked = entities.lock().unwrap();
+    regen_from_gpu(&mut locked, hitbox_buffer); // gpu can only write to hitboxes
+    let mut collisions = Vec::new();
+    for contact in contacts {
+        merge(
+            &mut collisions,
+            Collision {
+                entity: contact.entity as usize,
+                material: Some(contact.material),
+                other: None,
+                impulse: contact.impulse,
+            },
+        );
+    }
+    let settings = controls.lock().unwrap().settings;
+    step_physics(&mut locked, &settings, delta, &mut collisions); // before lua so scripts see where things ended up
+    drop(locked);
+    let collision_tables: Vec<Table> = {
+        let controls = controls.lock().unwrap(); // not held while lua runs, the bindings lock it too
+        collisions
+            .iter()
+            .map(|c| lua_funcs::collision_table(ctx, c, &controls.registry))
+            .collect()
+    };
+    *frame_info.lock().unwrap() = FrameInfo {
+        frame,
+        time,
+        delta,
+        collisions,
+    };
+    let checkpoint = controls.lock().unwrap().checkpoint();
+    let ran = run_scripts(ctx, collision_tables);
     // println!("tick worked");
-    // we have to apply the changes here because the rust lua crate I chose kind of sucks.
-    let RS_deltas = ctx.globals().get("RS_deltas");
-    if RS_deltas.is_ok() {
-        let RS_deltas: Table = RS_deltas.unwrap();
-        for elem in RS_deltas.pairs::<usize, Table>() {
-            let (_, value) = elem.unwrap();
-            let eid: usize = value.get(1).unwrap();
-            let cid: String = value.get(2).unwrap();
-            match &cid[..] {
-                "sprite.pos" => {
-                    // ugly match pattern
-                    let data1 = value.get(3).unwrap();
-                    let data2 = value.get(4).unwrap();
-                    entities[eid].sprite.pos = [data1, data2];
-                }
-                "sprite.size" => {
-                    let data1 = value.get(3).unwrap();
-                    let data2 = value.get(4).unwrap();
-                    entities[eid].sprite.size = [data1, data2];
-                }
-                "sprite.offset" => {
-                    let data1 = value.get(3).unwrap();
-                    let data2 = value.get(4).unwrap();
-                    entities[eid].sprite.offset = [data1, data2];
-                }
-                "sprite.scale" => {
-                    let data1 = value.get(3).unwrap();
-                    let data2 = value.get(4).unwrap();
-                    entities[eid].sprite.scale = [data1, data2];
-                }
-                "hitbox.pos" => {
-                    let data1 = value.get(3).unwrap();
-                    let data2 = value.get(4).unwrap();
-                    entities[eid].hitbox.pos = [data1, data2];
-                }
-                "hitbox.size" => {
-                    let data1 = value.get(3).unwrap();
-                    let data2 = value.get(4).unwrap();
-                    entities[eid].hitbox.size = [data1, data2];
-                }
-                "hitbox.mass" => {
-                    let data1 = value.get(3).unwrap();
-                    entities[eid].hitbox.mass = data1;
-                }
-                "hitbox.simulate" => {
-                    let data1 = value.get(3).unwrap();
-                    entities[eid].hitbox.simulate = if data1 { 1 } else { 0 };
-                }
-                "data" => {
-                    entities[eid].data = value.get(3).unwrap();
-                }
-                "deleted" => {
-                    entities[eid].deleted = value.get(3).unwrap();
-                    // can't pop because then eid changes
-                    entities[eid].hitbox.deleted = if value.get(3).unwrap() { 1 } else { 0 };
-                    entities[eid].sprite.deleted = if value.get(3).unwrap() { 1 } else { 0 };
-                }
-                _ => {
-                    panic!("invalid path")
-                }
+    let commands = {
+        let mut controls = controls.lock().unwrap();
+        if ran.is_err() {
+            controls.restore(checkpoint); // spawns, SetPhysics, pausing and the like go too
+        }
+        std::mem::take(&mut controls.commands)
+    };
+    let mut entities = entities.lock().unwrap();
+    for command in commands.into_iter().filter(|_| ran.is_ok()) {
+        match command {
+            Command::Set(eid, component) => component.apply(&mut entities[eid]),
+            Command::Create(eid) => {
+                entities[eid] = Entity {
+                    data: "clean".to_owned(), // mark the new entity as safe.
+                    ..Default::default()
+                };
             }
         }
     }
-    let RS_created: Table = ctx.globals().get("RS_created").unwrap();
-    for elem in RS_created.pairs() {
-        let (_, value): (u32, i32) = elem.unwrap();
-        if value == -1 {
-            panic!("Ran out of entities to write into");
-        } else {
-            entities[value as usize] = Entity {
-                data: "clean".to_owned(), // mark the new entity as safe.
-                ..Default::default()
-            };
+    regen_from_cpu(&entities, sprite_buffer, hitbox_buffer);
+    ran
+}
+
+/// OnCollision for every contact then tick.lua, stopping at the first error.
+fn run_scripts(ctx: Context, collision_tables: Vec<Table>) -> rlua::Result<()> {
+    let globals = ctx.globals();
+    if let Some(on_collision) = globals.get::<_, Option<Function>>("OnCollision")? {
+        for table in collision_tables {
+            on_collision.call::<_, ()>(table)?; // edits queue up like tick.lua's
         }
     }
-    regen_from_cpu(entities, sprite_buffer, hitbox_buffer);
+    // nil when tick.lua didn't compile, the window already reported why
+    if let Some(tick) = globals.get::<_, Option<Function>>("RS_tick_handle")? {
+        tick.call::<_, ()>(())?;
+    }
+    Ok(())
 }
@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+
+use rlua::{Context, Table, Value};
+
+use vulkano::padded::Padded;
+
+use crate::lua_funcs;
+
+use super::sand::{
+    self,
+    sand_shader::{Material, MaterialType, Reaction},
+    TYPE_PADDING,
+};
+
+impl Default for MaterialType {
+    fn default() -> Self {
+        MaterialType {
+            colour: [1f32, 0f32, 1f32],
+            mass: 1f32,
+            friction: 0f32,
+            stable: 0f32,
+            gas: 0,
+            tags: 0,
+            temperature: 20f32,
+            conductivity: 0.1f32,
+            melt_point: 0f32,
+            melt_into: 0,
+            freeze_point: 0f32,
+            freeze_into: 0,
+        }
+    }
+}
+
+/// Every named material from data/materials.lua, `types` is uploaded in order so keep it indexed by id.
+#[derive(Clone, Debug, Default)]
+pub struct MaterialRegistry {
+    pub types: Vec<MaterialType>,
+    pub names: HashMap<String, u32>,
+    pub tags: HashMap<String, u32>, // name -> bit
+    pub reactions: Vec<Reaction>,
+}
+
+impl MaterialRegistry {
+    pub fn load(
+        ctx: Context,
+        tags_path: &str,
+        materials_path: &str,
+        reactions_path: &str,
+    ) -> MaterialRegistry {
+        let mut registry = MaterialRegistry {
+            types: vec![MaterialType::default()], // id 0 is the fallback for particles without a material
+            ..Default::default()
+        };
+
+        let data: Table = lua_funcs::eval_script(ctx, tags_path);
+        lua_funcs::exit_on_error(registry.read_tags(data));
+        let data: Table = lua_funcs::eval_script(ctx, materials_path);
+        lua_funcs::exit_on_error(registry.read_materials(data));
+        let data: Table = lua_funcs::eval_script(ctx, reactions_path);
+        lua_funcs::exit_on_error(registry.read_reactions(data));
+        if registry.reactions.is_empty() {
+            // buffers can't be empty, a reaction on no tags never fires
+            registry.reactions.push(Reaction {
+                tag: 0,
+                touching: 0,
+                into: 0,
+                chance: 0f32,
+            });
+        }
+        registry
+    }
+
+    pub fn gpu_types(&self) -> Vec<Padded<MaterialType, TYPE_PADDING>> {
+        self.types.iter().map(|kind| Padded(*kind)).collect()
+    }
+
+    pub fn id(&self, name: &str) -> rlua::Result<u32> {
+        self.lookup(name)
+            .ok_or_else(|| rlua::Error::RuntimeError(format!("unknown material {name}")))
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<u32> {
+        self.names.get(name).copied()
+    }
+
+    /// The name a material was given in data/materials.lua, nil in lua for the fallback id 0.
+    pub fn name(&self, id: u32) -> Option<&str> {
+        self.names
+            .iter()
+            .find(|(_, &named)| named == id)
+            .map(|(name, _)| &name[..])
+    }
+
+    /// Fills in the shared defaults for a particle that asked for `material = name`.
+    pub fn apply(&self, name: &str, material: &mut Material) -> rlua::Result<()> {
+        let id = self.id(name)?;
+        let kind = self.types[id as usize];
+        material.id = id;
+        material.colour = kind.colour;
+        material.mass = kind.mass;
+        material.stable = kind.stable;
+        material.gas = kind.gas;
+        material.tags = kind.tags;
+        material.temperature = kind.temperature;
+        Ok(())
+    }
+
+    /// Builds one particle from an init_world.lua entry, SpawnParticle takes the same table.
+    pub fn particle(&self, value: &Table) -> rlua::Result<Material> {
+        let mut building_mat = Material {
+            ..Default::default()
+        };
+        if let Ok(name) = value.get::<&str, String>("material") {
+            self.apply(&name, &mut building_mat)?; // defaults first so the particle can override them
+        }
+        handle_lua_elem!(u32, "id", id, value, building_mat);
+        handle_lua_elem!(f32, "mass", mass, value, building_mat);
+        handle_lua_elem!(f32, "force", force, value, building_mat);
+        handle_lua_elem!(f32, "stable", stable, value, building_mat);
+        handle_lua_elem!(f32, "temperature", temperature, value, building_mat);
+        match value.get::<&str, Value>("tags") {
+            Ok(Value::Nil) | Err(_) => {}
+            Ok(tags) => building_mat.tags = self.tag_mask(tags)?,
+        }
+        handle_lua_elem!(u32, "gas", gas, value, building_mat);
+        if let Ok(name) = value.get::<&str, String>("gas") {
+            building_mat.gas = gas_mode(&name)?;
+        }
+        let read_vec = |name| lua_funcs::read_vec::<2>(value, name);
+        if let Some(colour) = lua_funcs::read_vec::<3>(value, "colour")? {
+            building_mat.colour = colour;
+        }
+        if let Some(pos) = read_vec("pos")? {
+            building_mat.pos = pos;
+        }
+        if let Some(vel) = read_vec("vel")? {
+            building_mat.vel = vel;
+        }
+        // statics hold where they spawn unless told otherwise
+        building_mat.target = read_vec("target")?.unwrap_or(building_mat.pos);
+        Ok(building_mat)
+    }
+
+    /// Tags can be given as a raw bit mask, a single name or a list of names, nil is no tags.
+    pub fn tag_mask(&self, value: Value) -> rlua::Result<u32> {
+        match value {
+            Value::Nil => Ok(0),
+            Value::Integer(mask) => Ok(mask as u32),
+            Value::Number(mask) => Ok(mask as u32),
+            Value::String(name) => self.tag(name.to_str()?),
+            Value::Table(names) => names
+                .sequence_values::<String>()
+                .try_fold(0, |mask, name| Ok(mask | self.tag(&name?)?)),
+            _ => Err(rlua::Error::RuntimeError(
+                "tags must be a number, name or list of names".to_owned(),
+            )),
+        }
+    }
+
+    fn tag(&self, name: &str) -> rlua::Result<u32> {
+        self.tags
+            .get(name)
+            .copied()
+            .ok_or_else(|| rlua::Error::RuntimeError(format!("unknown tag {name}")))
+    }
+
+    fn read_tags(&mut self, data: Table) -> rlua::Result<()> {
+        for elem in data.sequence_values::<String>() {
+            let bit = self.tags.len();
+            if bit >= 32 {
+                return Err(rlua::Error::RuntimeError(
+                    "only 32 tags fit in Material.tags".to_owned(),
+                ));
+            }
+            self.tags.insert(elem?, 1 << bit);
+        }
+        Ok(())
+    }
+
+    fn read_materials(&mut self, data: Table) -> rlua::Result<()> {
+        let mut entries = Vec::new();
+        for elem in data.pairs::<String, Table>() {
+            let (name, value) = elem?;
+            let id: u32 = value
+                .get("id")
+                .map_err(|_| rlua::Error::RuntimeError(format!("material {name} has no id")))?;
+            self.names.insert(name, id);
+            entries.push((id, value));
+        }
+        for (id, value) in entries {
+            // second pass so melt_into and freeze_into can name materials defined further down
+            if id as usize >= self.types.len() {
+                self.types.resize(id as usize + 1, MaterialType::default());
+            }
+            self.types[id as usize] = self.read_type(&value)?;
+        }
+        Ok(())
+    }
+
+    fn read_reactions(&mut self, data: Table) -> rlua::Result<()> {
+        for elem in data.sequence_values::<Table>() {
+            let reaction = self.read_reaction(&elem?)?;
+            self.reactions.push(reaction);
+        }
+        Ok(())
+    }
+
+    fn read_reaction(&self, value: &Table) -> rlua::Result<Reaction> {
+        let into: String = value.get("into")?;
+        Ok(Reaction {
+            tag: self.tag_mask(value.get("tag")?)?,
+            touching: self.tag_mask(value.get("touching")?)?,
+            into: self.id(&into)?,
+            chance: value.get("chance").unwrap_or(1f32),
+        })
+    }
+
+    fn read_type(&self, value: &Table) -> rlua::Result<MaterialType> {
+        let mut kind = MaterialType::default();
+        if let Some(colour) = lua_funcs::read_vec::<3>(value, "colour")? {
+            kind.colour = colour;
+        }
+        if let Ok(mass) = value.get("mass") {
+            kind.mass = mass;
+        }
+        if let Ok(friction) = value.get("friction") {
+            kind.friction = friction;
+        }
+        if let Ok(stable) = value.get("stable") {
+            kind.stable = stable;
+        }
+        if let Ok(gas) = value.get("gas") {
+            kind.gas = gas;
+        } else if let Ok(name) = value.get::<&str, String>("gas") {
+            kind.gas = gas_mode(&name)?;
+        }
+        kind.tags = self.tag_mask(value.get("tags")?)?;
+        if let Ok(temperature) = value.get("temperature") {
+            kind.temperature = temperature;
+        }
+        if let Ok(conductivity) = value.get("conductivity") {
+            kind.conductivity = conductivity;
+        }
+        if let Ok(melt_point) = value.get("melt_point") {
+            kind.melt_point = melt_point;
+            kind.melt_into = self.id(&value.get::<&str, String>("melt_into")?)?;
+        }
+        if let Ok(freeze_point) = value.get("freeze_point") {
+            kind.freeze_point = freeze_point;
+            kind.freeze_into = self.id(&value.get::<&str, String>("freeze_into")?)?;
+        }
+        Ok(kind)
+    }
+}
+
+fn gas_mode(name: &str) -> rlua::Result<u32> {
+    sand::gas_from_name(name)
+        .ok_or_else(|| rlua::Error::RuntimeError(format!("unknown gas mode {name}")))
+}
@@ -1,2 +1,4 @@
 pub mod sand;
-pub mod ecs;
\ No newline at end of file
+pub mod ecs;
+pub mod materials;
+pub mod terrain;
\ No newline at end of file
@@ -1,17 +1,33 @@
 use std::sync::Arc;
 
+use rlua::{Context, Function, Table, Value};
+
 use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage};
 use vulkano::buffer::{BufferContents, Subbuffer};
-use vulkano::command_buffer::{CommandBufferExecFuture, PrimaryAutoCommandBuffer};
-use vulkano::device::{Device, Queue};
+use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilder, CommandBufferExecFuture, CommandBufferUsage, CopyBufferInfo,
+    PrimaryAutoCommandBuffer, PrimaryCommandBufferAbstract,
+};
+use vulkano::device::{Device, DeviceOwned, Queue};
+use vulkano::format::Format;
+use vulkano::image::view::ImageView;
+use vulkano::image::{ImageDimensions, ImmutableImage, MipmapsCount};
 use vulkano::padded::Padded;
+use vulkano::sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo};
 use vulkano::sync::future::{FenceSignalFuture, NowFuture};
+use vulkano::sync::GpuFuture;
 
 use crate::deploy_shader;
+use crate::lua_funcs;
+use crate::simulation::materials::MaterialRegistry;
+use crate::simulation::terrain::Terrain;
 use crate::window::init::fragment_shader;
 use vulkano::memory::allocator::{AllocationCreateInfo, MemoryAllocator, MemoryUsage};
 
-use self::sand_shader::Hitbox;
+use self::sand_shader::{Clock, GridCell, Hitbox, Material, WorldSettings};
+
+pub mod cpu;
 
 pub mod sand_shader {
     vulkano_shaders::shader! {
@@ -21,7 +37,34 @@ pub mod sand_shader {
     }
 }
 
-pub const PADDING: usize = 4;
+pub const PADDING: usize = 12; // Material is 68 bytes on an 80 byte stride
+pub const TYPE_PADDING: usize = 8; // MaterialType is 56 bytes on a 64 byte stride
+pub const HITBOX_PADDING: usize = 0; // Hitbox is 48 bytes, already a multiple of its vec2 alignment
+
+// MUST BE KEPT IN SYNC WITH GLSL VERSION
+pub const STAGE_CLEAR: u32 = 0;
+pub const STAGE_COUNT: u32 = 1;
+pub const STAGE_SCAN: u32 = 2;
+pub const STAGE_SCATTER: u32 = 3;
+pub const STAGE_SORT: u32 = 4;
+pub const STAGE_SIMULATE: u32 = 5;
+pub const STAGE_REDUCE: u32 = 6;
+pub const GAS_NORMAL: u32 = 0;
+pub const GAS_ANTIGRAVITY: u32 = 1;
+pub const GAS_FLOATING: u32 = 2;
+pub const GAS_BUOYANT: u32 = 3;
+pub const BOUNDARY_WALL: u32 = 0;
+pub const BOUNDARY_WRAP: u32 = 1;
+pub const BOUNDARY_OPEN: u32 = 2;
+pub const SHAPE_BOX: u32 = 0;
+pub const SHAPE_CIRCLE: u32 = 1;
+pub const SHAPE_ROTATED: u32 = 2;
+pub const SUBSTEPS_PER_TICK: u32 = 2; // one command buffer is a ping and a pong
+pub const BASE_RATE: f32 = 300.0; // 5 substeps a frame at 60fps, the rate the window loop originally ran
+pub const GRID_SIZE: u32 = 50;
+pub const GRID_CELLS: u32 = GRID_SIZE * GRID_SIZE;
+pub const IMPULSE_SCALE: f32 = 4096.0;
+pub const CONTACT_FIELDS: usize = 3;
 
 impl Default for sand_shader::Material {
     fn default() -> sand_shader::Material {
@@ -36,8 +79,281 @@ impl Default for sand_shader::Material {
             stable: 0f32,
             tags: 0,
             gas: 0,
+            temperature: 20f32,
+            alive: 1,
+        }
+    }
+}
+
+/// Which implementation runs the physics, cpu is the slow reference in sand::cpu.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    Gpu,
+    Cpu,
+}
+
+pub fn backend_from_name(name: &str) -> Option<Backend> {
+    match name {
+        "gpu" => Some(Backend::Gpu),
+        "cpu" => Some(Backend::Cpu),
+        _ => None,
+    }
+}
+
+/// What happens to particles at the edges, per axis.
+pub fn boundary_from_name(name: &str) -> Option<u32> {
+    match name {
+        "wall" => Some(BOUNDARY_WALL),
+        "wrap" => Some(BOUNDARY_WRAP),
+        "open" => Some(BOUNDARY_OPEN),
+        _ => None,
+    }
+}
+
+/// Hitbox shapes by name, so scripts don't need the numbers.
+pub fn shape_from_name(name: &str) -> Option<u32> {
+    match name {
+        "box" => Some(SHAPE_BOX),
+        "circle" => Some(SHAPE_CIRCLE),
+        "rotated" => Some(SHAPE_ROTATED),
+        _ => None,
+    }
+}
+
+/// The name shape_from_name takes for a SHAPE_* number.
+pub fn shape_name(shape: u32) -> Option<&'static str> {
+    match shape {
+        SHAPE_BOX => Some("box"),
+        SHAPE_CIRCLE => Some("circle"),
+        SHAPE_ROTATED => Some("rotated"),
+        _ => None,
+    }
+}
+
+/// A hitbox shape given by name or SHAPE_* number, anything else is an error rather than a shape
+/// the shaders don't know.
+pub fn shape_from_lua(value: &Value) -> rlua::Result<u32> {
+    let (shape, given) = match value {
+        Value::String(name) => (shape_from_name(name.to_str()?), name.to_str()?.to_owned()),
+        Value::Integer(n) => (u32::try_from(*n).ok(), n.to_string()),
+        Value::Number(n) if n.fract() == 0.0 && *n >= 0.0 => (Some(*n as u32), n.to_string()),
+        other => (None, other.type_name().to_owned()),
+    };
+    match shape {
+        Some(shape) if shape <= SHAPE_ROTATED => Ok(shape),
+        _ => Err(rlua::Error::RuntimeError(format!(
+            "unknown hitbox shape {given}, expected \"box\", \"circle\" or \"rotated\""
+        ))),
+    }
+}
+
+/// Lets lua name gas modes instead of using the raw numbers.
+pub fn gas_from_name(name: &str) -> Option<u32> {
+    match name {
+        "normal" => Some(GAS_NORMAL),
+        "antigravity" => Some(GAS_ANTIGRAVITY),
+        "floating" => Some(GAS_FLOATING),
+        "buoyant" => Some(GAS_BUOYANT),
+        _ => None,
+    }
+}
+
+impl Default for sand_shader::WorldSettings {
+    fn default() -> Self {
+        sand_shader::WorldSettings {
+            gravity: 0.0005,
+            radius: 0.02,
+            max_speed: 0.1,
+            damping: 0.999,
+            jitter: 0.0001,
+            wall_margin: 0.005,
+            wall_margin_left: 0.0005,
+            wall_push: 0.0006,
+            dt: 1f32 / 300f32,
+            size: [1f32, 1f32],
+            boundary_x: BOUNDARY_WALL,
+            boundary_y: BOUNDARY_WALL,
+            restitution: 0f32,
+        }
+    }
+}
+
+/// Settings for the gpu, how many ticks a slow frame is allowed to run to catch up and the seed if one is set.
+pub fn load_world_settings(ctx: Context, path: &str) -> (WorldSettings, u32, Option<u32>) {
+    let data: Table = lua_funcs::eval_script(ctx, path);
+    let mut settings = WorldSettings::default();
+    lua_funcs::exit_on_error(apply_world_settings(&mut settings, &data));
+    (
+        settings,
+        data.get("max_catch_up").unwrap_or(10),
+        data.get("seed").ok(),
+    )
+}
+
+/// Seeds lua's math.random so init scripts build the same world every run with the same seed.
+pub fn seed_lua(ctx: Context, seed: u32) {
+    let math: Table = ctx.globals().get("math").unwrap();
+    let randomseed: Function = math.get("randomseed").unwrap();
+    randomseed.call::<_, ()>(seed).unwrap();
+}
+
+/// Overwrites whatever fields the table has, anything missing is left alone. Nothing is changed
+/// when a field is invalid.
+pub fn apply_world_settings(current: &mut WorldSettings, table: &Table) -> rlua::Result<()> {
+    let mut settings = *current; // written back once every field checked out
+    let fields: [(&str, &mut f32); 10] = [
+        ("gravity", &mut settings.gravity),
+        ("radius", &mut settings.radius),
+        ("max_speed", &mut settings.max_speed),
+        ("damping", &mut settings.damping),
+        ("jitter", &mut settings.jitter),
+        ("wall_margin", &mut settings.wall_margin),
+        ("wall_margin_left", &mut settings.wall_margin_left),
+        ("wall_push", &mut settings.wall_push),
+        ("dt", &mut settings.dt),
+        ("restitution", &mut settings.restitution),
+    ];
+    for (name, field) in fields {
+        let value = table
+            .get::<&str, Option<f32>>(name)
+            .map_err(|_| rlua::Error::RuntimeError(format!("{name} must be a number")))?;
+        if let Some(value) = value {
+            *field = value;
+        }
+    }
+    if settings.dt <= 0.0 || settings.dt.is_nan() {
+        // a tick that doesn't move time would stop entities and stall the timestep
+        return Err(rlua::Error::RuntimeError(format!(
+            "dt must be positive, got {}",
+            settings.dt
+        )));
+    }
+    if let Some(size) = lua_funcs::read_vec::<2>(table, "size")? {
+        if size.iter().any(|&side| side <= 0.0 || side.is_nan()) {
+            return Err(rlua::Error::RuntimeError(format!(
+                "size must be two positive numbers, got {size:?}"
+            )));
+        }
+        settings.size = size;
+    }
+    let boundary = |key: &str| -> rlua::Result<Option<u32>> {
+        let name = table
+            .get::<&str, Option<String>>(key)
+            .map_err(|_| rlua::Error::RuntimeError(format!("{key} must be a boundary name")))?;
+        let Some(name) = name else {
+            return Ok(None);
+        };
+        boundary_from_name(&name).map(Some).ok_or_else(|| {
+            rlua::Error::RuntimeError(format!(
+                "unknown boundary mode {name}, expected wall, wrap or open"
+            ))
+        })
+    };
+    if let Some(mode) = boundary("boundary")? {
+        // both axes, boundary_x and boundary_y override it
+        settings.boundary_x = mode;
+        settings.boundary_y = mode;
+    }
+    if let Some(mode) = boundary("boundary_x")? {
+        settings.boundary_x = mode;
+    }
+    if let Some(mode) = boundary("boundary_y")? {
+        settings.boundary_y = mode;
+    }
+    let max_radius = settings.size[0].min(settings.size[1]) / GRID_SIZE as f32;
+    if settings.radius > max_radius {
+        println!(
+            "radius {} is bigger than a grid cell, clamping to {max_radius}",
+            settings.radius
+        );
+        settings.radius = max_radius; // the shader only looks one cell away
+    }
+    *current = settings;
+    Ok(())
+}
+
+/// Tracks which world slots hold a particle so lua can spawn and destroy them, the window uploads
+/// the queued slot writes and grows the gpu buffers after tick.lua has run.
+#[derive(Clone, Debug, Default)]
+pub struct ParticlePool {
+    pub capacity: u32, // slots on the gpu, always a whole number of workgroups
+    pub next: u32,     // slots from here up have never been used
+    free: Vec<u32>,
+    pending: Vec<(u32, Material)>,
+}
+
+impl ParticlePool {
+    /// Fills `world` up to a whole workgroup with free slots, everything loaded starts alive.
+    pub fn pad(world: &mut Vec<Padded<Material, PADDING>>) -> ParticlePool {
+        let next = world.len() as u32;
+        let capacity = workgroup_ceil(next);
+        if capacity != next {
+            println!(
+                "world has {next} particles, padding to {capacity} so the last workgroup is full"
+            );
+        }
+        world.resize(capacity as usize, Padded(ParticlePool::dead()));
+        ParticlePool {
+            capacity,
+            next,
+            ..Default::default()
+        }
+    }
+
+    /// What a free slot holds, the shader skips anything with alive == 0.
+    pub fn dead() -> Material {
+        Material {
+            alive: 0,
+            ..Default::default()
         }
     }
+
+    pub fn spawn(&mut self, mut material: Material) -> u32 {
+        material.alive = 1;
+        let idx = self.free.pop().unwrap_or_else(|| {
+            self.next += 1;
+            self.next - 1
+        });
+        self.pending.push((idx, material));
+        idx
+    }
+
+    /// False if nothing was alive at `idx`.
+    pub fn destroy(&mut self, idx: u32) -> bool {
+        if idx >= self.next || self.free.contains(&idx) {
+            return false;
+        }
+        self.free.push(idx);
+        self.pending.push((idx, ParticlePool::dead()));
+        true
+    }
+
+    /// Frees slots the gpu already emptied, particles that left through an open boundary.
+    pub fn release(&mut self, slots: &[u32]) {
+        for &idx in slots {
+            if idx < self.next && !self.free.contains(&idx) {
+                self.free.push(idx);
+            }
+        }
+    }
+
+    /// Bumps the capacity if spawns ran past it, returns the new one when the buffers need remaking.
+    pub fn grow(&mut self) -> Option<u32> {
+        if self.next <= self.capacity {
+            return None;
+        }
+        // doubling so a steady stream of spawns doesn't reallocate every frame
+        self.capacity = workgroup_ceil(self.next.max(self.capacity * 2));
+        Some(self.capacity)
+    }
+
+    pub fn take_pending(&mut self) -> Vec<(u32, Material)> {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+fn workgroup_ceil(count: u32) -> u32 {
+    count.div_ceil(64).max(1) * 64
 }
 
 impl Default for Hitbox {
@@ -49,6 +365,9 @@ impl Default for Hitbox {
             mass: 1f32,
             simulate: 1,
             deleted: 0,
+            shape: SHAPE_BOX,
+            radius: 0f32,
+            angle: 0f32,
         }
     }
 }
@@ -68,7 +387,9 @@ pub fn upload_device_buffer(
     Buffer::new_slice(
         memory_allocator,
         BufferCreateInfo {
-            usage: BufferUsage::STORAGE_BUFFER | BufferUsage::TRANSFER_DST, // you need to be able to copy to a device only buffer so this is fine
+            usage: BufferUsage::STORAGE_BUFFER
+                | BufferUsage::TRANSFER_DST
+                | BufferUsage::TRANSFER_SRC, // you need to be able to copy to a device only buffer so this is fine, src is for growing
             ..Default::default()
         },
         AllocationCreateInfo {
@@ -79,6 +400,338 @@ pub fn upload_device_buffer(
     )
     .expect("failed to create buffer")
 }
+
+/// Copies the world into a buffer with room for `capacity` particles, the new tail is free slots.
+pub fn grow_world_buffer(
+    memory_allocator: &(impl MemoryAllocator + ?Sized),
+    command_buffer_allocator: &StandardCommandBufferAllocator,
+    queue: &Arc<Queue>,
+    old: &Subbuffer<[Padded<Material, PADDING>]>,
+    capacity: u32,
+) -> Subbuffer<[Padded<Material, PADDING>]> {
+    let new = upload_device_buffer(memory_allocator, capacity as u64);
+    let filler = upload_transfer_source_buffer(
+        vec![Padded(ParticlePool::dead()); (capacity as u64 - old.len()) as usize],
+        memory_allocator,
+    );
+    copy_and_wait(command_buffer_allocator, queue, |builder| {
+        builder
+            .copy_buffer(CopyBufferInfo::buffers(
+                old.clone(),
+                new.clone().slice(..old.len()),
+            ))
+            .unwrap()
+            .copy_buffer(CopyBufferInfo::buffers(
+                filler,
+                new.clone().slice(old.len()..),
+            ))
+            .unwrap();
+    });
+    new
+}
+
+/// Uploads the slots lua spawned into or destroyed this frame.
+pub fn write_particles(
+    memory_allocator: &(impl MemoryAllocator + ?Sized),
+    command_buffer_allocator: &StandardCommandBufferAllocator,
+    queue: &Arc<Queue>,
+    world: &Subbuffer<[Padded<Material, PADDING>]>,
+    writes: Vec<(u32, Material)>,
+) {
+    if writes.is_empty() {
+        return;
+    }
+    let slots: Vec<u64> = writes.iter().map(|(idx, _)| *idx as u64).collect();
+    let staging = upload_transfer_source_buffer(
+        writes.into_iter().map(|(_, m)| Padded(m)).collect(),
+        memory_allocator,
+    );
+    copy_and_wait(command_buffer_allocator, queue, |builder| {
+        for (n, idx) in slots.into_iter().enumerate() {
+            // one copy each so a slot written twice in a frame keeps the last write
+            builder
+                .copy_buffer(CopyBufferInfo::buffers(
+                    staging.clone().slice(n as u64..n as u64 + 1),
+                    world.clone().slice(idx..idx + 1),
+                ))
+                .unwrap();
+        }
+    });
+}
+
+/// Replaces the front of the world with `particles`, the cpu backend's way back onto the gpu.
+pub fn upload_world_buffer(
+    memory_allocator: &(impl MemoryAllocator + ?Sized),
+    command_buffer_allocator: &StandardCommandBufferAllocator,
+    queue: &Arc<Queue>,
+    world: &Subbuffer<[Padded<Material, PADDING>]>,
+    particles: Vec<Material>,
+) {
+    let staging = upload_transfer_source_buffer(
+        particles.into_iter().map(Padded).collect(),
+        memory_allocator,
+    );
+    copy_and_wait(command_buffer_allocator, queue, |builder| {
+        builder
+            .copy_buffer(CopyBufferInfo::buffers(
+                staging.clone(),
+                world.clone().slice(..staging.len()),
+            ))
+            .unwrap();
+    });
+}
+
+/// Reads the whole world back, only the cpu backend and --compare-backends need this.
+pub fn download_world_buffer(
+    memory_allocator: &(impl MemoryAllocator + ?Sized),
+    command_buffer_allocator: &StandardCommandBufferAllocator,
+    queue: &Arc<Queue>,
+    world: &Subbuffer<[Padded<Material, PADDING>]>,
+) -> Vec<Material> {
+    let host: Subbuffer<[Padded<Material, PADDING>]> = Buffer::new_slice(
+        memory_allocator,
+        BufferCreateInfo {
+            usage: BufferUsage::TRANSFER_DST,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            usage: MemoryUsage::Download,
+            ..Default::default()
+        },
+        world.len(),
+    )
+    .expect("failed to create buffer");
+    copy_and_wait(command_buffer_allocator, queue, |builder| {
+        builder
+            .copy_buffer(CopyBufferInfo::buffers(world.clone(), host.clone()))
+            .unwrap();
+    });
+    let particles = host.read().unwrap().iter().map(|m| **m).collect();
+    particles
+}
+
+/// Waits for the upload so the first tick can sample it, compute and render share the sampler.
+pub fn upload_terrain_image(
+    memory_allocator: &(impl MemoryAllocator + ?Sized),
+    command_buffer_allocator: &StandardCommandBufferAllocator,
+    queue: &Arc<Queue>,
+    terrain: &Terrain,
+) -> (Arc<ImageView<ImmutableImage>>, Arc<Sampler>) {
+    let image = copy_and_wait(command_buffer_allocator, queue, |builder| {
+        ImmutableImage::from_iter(
+            memory_allocator,
+            terrain.solid.clone(),
+            ImageDimensions::Dim2d {
+                width: terrain.width,
+                height: terrain.height,
+                array_layers: 1,
+            },
+            MipmapsCount::One,
+            Format::R8_UNORM,
+            builder,
+        )
+        .unwrap()
+    });
+    let sampler = Sampler::new(
+        queue.device().clone(),
+        SamplerCreateInfo {
+            mag_filter: Filter::Nearest,
+            min_filter: Filter::Nearest,
+            address_mode: [SamplerAddressMode::ClampToEdge; 3],
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    (ImageView::new_default(image).unwrap(), sampler)
+}
+
+/// Runs the cpu reference for one tick against the gpu buffers so the renderer doesn't need to know,
+/// returns the slots that left through an open boundary and the grains that touched entities.
+pub fn tick_on_cpu(
+    memory_allocator: &(impl MemoryAllocator + ?Sized),
+    command_buffer_allocator: &StandardCommandBufferAllocator,
+    queue: &Arc<Queue>,
+    world: &Subbuffer<[Padded<Material, PADDING>]>,
+    hitbox_buffer: &Subbuffer<[Padded<Hitbox, HITBOX_PADDING>]>,
+    registry: &MaterialRegistry,
+    settings: &WorldSettings,
+    terrain: &Terrain,
+    clock: &Clock,
+    count: u32,
+) -> (Vec<u32>, Vec<Contact>) {
+    let mut particles =
+        download_world_buffer(memory_allocator, command_buffer_allocator, queue, world);
+    let mut hitboxes: Vec<Hitbox> = hitbox_buffer.read().unwrap().iter().map(|h| **h).collect();
+    let touched = cpu::tick(
+        &mut particles,
+        &mut hitboxes,
+        registry,
+        settings,
+        terrain,
+        clock,
+        count as usize,
+    );
+    for (dst, src) in hitbox_buffer.write().unwrap().iter_mut().zip(hitboxes) {
+        **dst = src;
+    }
+    upload_world_buffer(
+        memory_allocator,
+        command_buffer_allocator,
+        queue,
+        world,
+        particles,
+    );
+    touched
+}
+
+fn copy_and_wait<R>(
+    command_buffer_allocator: &StandardCommandBufferAllocator,
+    queue: &Arc<Queue>,
+    record: impl FnOnce(&mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>) -> R,
+) -> R {
+    let mut builder = AutoCommandBufferBuilder::primary(
+        command_buffer_allocator,
+        queue.queue_family_index(),
+        CommandBufferUsage::OneTimeSubmit,
+    )
+    .unwrap();
+    let recorded = record(&mut builder);
+    builder
+        .build()
+        .unwrap()
+        .execute(queue.clone())
+        .unwrap()
+        .then_signal_fence_and_flush()
+        .unwrap()
+        .wait(None)
+        .unwrap();
+    recorded
+}
+
+/// Slots that left through an open boundary, element 0 is how many follow.
+pub fn upload_killed_buffer(
+    memory_allocator: &(impl MemoryAllocator + ?Sized),
+    capacity: u32,
+) -> Subbuffer<[u32]> {
+    upload_standard_buffer(vec![0u32; capacity as usize + 1], memory_allocator) // a particle can only leave once a tick
+}
+
+/// Fixed point pushes from grains, an x and a y per hitbox, STAGE_REDUCE empties it every tick.
+pub fn upload_impulse_buffer(
+    memory_allocator: &(impl MemoryAllocator + ?Sized),
+    hitboxes: u64,
+) -> Subbuffer<[i32]> {
+    upload_standard_buffer(vec![0i32; hitboxes as usize * 2], memory_allocator)
+}
+
+/// Empties the killed list, call after every tick while the gpu is idle.
+pub fn drain_killed(buffer: &Subbuffer<[u32]>) -> Vec<u32> {
+    let mut list = buffer.write().unwrap();
+    let count = (list[0] as usize).min(list.len() - 1);
+    list[0] = 0;
+    list[1..=count].to_vec()
+}
+
+/// Every material that pushed an entity over one tick, summed in fixed point on the gpu.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Contact {
+    pub entity: u32,
+    pub material: u32,
+    pub impulse: [f32; 2], // what the grains of this material pushed the entity by
+}
+
+/// CONTACT_FIELDS totals for every material against every hitbox, see DataContacts.
+pub fn upload_contacts_buffer(
+    memory_allocator: &(impl MemoryAllocator + ?Sized),
+    hitboxes: u64,
+    materials: u64,
+) -> Subbuffer<[i32]> {
+    upload_standard_buffer(
+        vec![0i32; (hitboxes * materials) as usize * CONTACT_FIELDS],
+        memory_allocator,
+    )
+}
+
+/// Empties the contact totals like drain_killed, call after every tick while the gpu is idle.
+pub fn drain_contacts(buffer: &Subbuffer<[i32]>, materials: usize) -> Vec<Contact> {
+    take_contacts(&mut buffer.write().unwrap(), materials)
+}
+
+/// Contacts in entity then material order and zeroes the totals, shared with sand::cpu.
+pub fn take_contacts(totals: &mut [i32], materials: usize) -> Vec<Contact> {
+    let mut contacts = Vec::new();
+    for (slot, fields) in totals.chunks_exact_mut(CONTACT_FIELDS).enumerate() {
+        if fields[2] != 0 {
+            contacts.push(Contact {
+                entity: (slot / materials) as u32,
+                material: (slot % materials) as u32,
+                impulse: [
+                    fields[0] as f32 / IMPULSE_SCALE,
+                    fields[1] as f32 / IMPULSE_SCALE,
+                ],
+            });
+        }
+        fields.fill(0);
+    }
+    contacts
+}
+
+/// Small host visible uniforms like the world settings and active particle count.
+pub fn upload_uniform_buffer<T>(
+    data: T,
+    memory_allocator: &(impl MemoryAllocator + ?Sized),
+) -> Subbuffer<T>
+where
+    T: BufferContents,
+{
+    Buffer::from_data(
+        memory_allocator,
+        BufferCreateInfo {
+            usage: BufferUsage::UNIFORM_BUFFER,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            usage: MemoryUsage::Upload,
+            ..Default::default()
+        },
+        data,
+    )
+    .expect("failed to create buffer")
+}
+
+/// Cell table and sorted index list for the spatial hash, both only touched by the gpu.
+pub fn upload_grid_buffers(
+    memory_allocator: &(impl MemoryAllocator + ?Sized),
+    particles: u64,
+) -> (Subbuffer<[GridCell]>, Subbuffer<[u32]>) {
+    (
+        device_only_buffer(memory_allocator, GRID_CELLS as u64),
+        device_only_buffer(memory_allocator, particles),
+    )
+}
+
+fn device_only_buffer<T>(
+    memory_allocator: &(impl MemoryAllocator + ?Sized),
+    size: u64,
+) -> Subbuffer<[T]>
+where
+    T: BufferContents,
+{
+    Buffer::new_slice(
+        memory_allocator,
+        BufferCreateInfo {
+            usage: BufferUsage::STORAGE_BUFFER,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            usage: MemoryUsage::DeviceOnly,
+            ..Default::default()
+        },
+        size,
+    )
+    .expect("failed to create buffer")
+}
+
 ///! Slow and generally shouldn't be used, use a device and transfer buffer with download.
 pub fn upload_standard_buffer<T>(
     data: Vec<T>,
@@ -120,3 +773,74 @@ pub fn upload_transfer_source_buffer(
     )
     .expect("failed to create buffer")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(particles: usize) -> ParticlePool {
+        let mut world = vec![Padded(Material::default()); particles];
+        ParticlePool::pad(&mut world)
+    }
+
+    #[test]
+    fn pad_fills_whole_workgroups() {
+        let mut world = vec![Padded(Material::default()); 65];
+        let pool = ParticlePool::pad(&mut world);
+        assert_eq!((pool.capacity, pool.next), (128, 65));
+        assert_eq!(world.len(), 128);
+        assert_eq!(world[64].alive, 1);
+        assert_eq!(world[65].alive, 0);
+        assert_eq!(self::pool(0).capacity, 64); // buffers can't be empty
+    }
+
+    #[test]
+    fn spawn_reuses_freed_slots() {
+        let mut pool = pool(3);
+        assert!(pool.destroy(1));
+        assert!(!pool.destroy(1)); // already free
+        assert!(!pool.destroy(3)); // never used
+        assert_eq!(pool.spawn(Material::default()), 1);
+        assert_eq!(pool.spawn(Material::default()), 3);
+        assert_eq!(pool.next, 4);
+    }
+
+    #[test]
+    fn release_frees_each_slot_once() {
+        let mut pool = pool(3);
+        pool.release(&[2, 2, 7]); // 7 was never handed out
+        assert!(!pool.destroy(2));
+        assert_eq!(pool.spawn(Material::default()), 2);
+        assert_eq!(pool.spawn(Material::default()), 3);
+    }
+
+    #[test]
+    fn later_writes_to_a_slot_win() {
+        let mut pool = pool(3);
+        pool.destroy(0);
+        let idx = pool.spawn(Material {
+            alive: 0, // spawn marks it alive whatever it was given
+            ..Default::default()
+        });
+        let pending = pool.take_pending();
+        assert_eq!(idx, 0);
+        assert_eq!(pending.len(), 2);
+        assert_eq!(pending.last().unwrap().0, 0);
+        assert_eq!(pending.last().unwrap().1.alive, 1);
+        assert!(pool.take_pending().is_empty());
+    }
+
+    #[test]
+    fn grow_doubles_to_whole_workgroups() {
+        let mut pool = pool(64);
+        assert_eq!(pool.grow(), None);
+        pool.spawn(Material::default());
+        assert_eq!(pool.grow(), Some(128));
+        assert_eq!(pool.grow(), None);
+        for _ in 0..200 {
+            pool.spawn(Material::default());
+        }
+        assert_eq!(pool.next, 265);
+        assert_eq!(pool.grow(), Some(320)); // past double, rounded up to the next workgroup
+    }
+}
@@ -0,0 +1,600 @@
+//! Plain rust copy of sand_particle.glsl so the physics can run and be checked without a vulkan device.
+//! MUST BE KEPT IN SYNC WITH sand_particle.glsl, the comments there explain the maths.
+
+use glam::Vec2;
+
+use crate::simulation::materials::MaterialRegistry;
+use crate::simulation::terrain::Terrain;
+
+use super::sand_shader::{Clock, Hitbox, Material, MaterialType, WorldSettings};
+use super::{
+    take_contacts, Contact, BASE_RATE, BOUNDARY_OPEN, BOUNDARY_WALL, BOUNDARY_WRAP, CONTACT_FIELDS,
+    GAS_ANTIGRAVITY, GAS_BUOYANT, GAS_NORMAL, GRID_CELLS, GRID_SIZE, IMPULSE_SCALE, SHAPE_CIRCLE,
+    SHAPE_ROTATED, SUBSTEPS_PER_TICK,
+};
+
+/// One tick, the same pair of substeps the gpu runs. Only the first `count` slots are simulated,
+/// returns the slots that left through an open boundary and the grains that pushed an entity,
+/// like the gpu's killed and contact lists.
+pub fn tick(
+    world: &mut [Material],
+    hitboxes: &mut [Hitbox],
+    registry: &MaterialRegistry,
+    settings: &WorldSettings,
+    terrain: &Terrain,
+    clock: &Clock,
+    count: usize,
+) -> (Vec<u32>, Vec<Contact>) {
+    let count = count.min(world.len());
+    let mut killed = Vec::new();
+    let mut impulses = vec![[0i32; 2]; hitboxes.len()];
+    let materials = registry.types.len();
+    let mut contacts = vec![0i32; hitboxes.len() * materials * CONTACT_FIELDS];
+    for substep in 0..SUBSTEPS_PER_TICK {
+        let src = world.to_vec();
+        let cells = bin(&src[..count], settings);
+        for (idx, particle) in world.iter_mut().enumerate().take(count) {
+            let rng = seed_random(clock, substep, idx as u32);
+            *particle = simulate(
+                idx,
+                &src,
+                &cells,
+                hitboxes,
+                &mut impulses,
+                &mut contacts,
+                registry,
+                settings,
+                terrain,
+                rng,
+            );
+            if particle.alive == 0 && src[idx].alive != 0 {
+                killed.push(idx as u32);
+            }
+        }
+    }
+    for (hitbox, impulse) in hitboxes.iter_mut().zip(impulses) {
+        // STAGE_REDUCE
+        hitbox.vel[0] += impulse[0] as f32 / IMPULSE_SCALE;
+        hitbox.vel[1] += impulse[1] as f32 / IMPULSE_SCALE;
+    }
+    (killed, take_contacts(&mut contacts, materials))
+}
+
+/// Particle indices per grid cell, pushing in index order gives the same order STAGE_SORT does.
+fn bin(src: &[Material], settings: &WorldSettings) -> Vec<Vec<u32>> {
+    let mut cells = vec![Vec::new(); GRID_CELLS as usize];
+    for (idx, m) in src.iter().enumerate() {
+        if m.alive != 0 {
+            cells[cell_index(Vec2::from(m.pos), settings)].push(idx as u32);
+        }
+    }
+    cells
+}
+
+fn pcg(v: u32) -> u32 {
+    let state = v.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+fn seed_random(clock: &Clock, substep: u32, idx: u32) -> u32 {
+    pcg(clock.seed
+        ^ pcg(clock
+            .tick
+            .wrapping_mul(SUBSTEPS_PER_TICK)
+            .wrapping_add(substep)
+            ^ pcg(idx)))
+}
+
+fn random(state: &mut u32) -> f32 {
+    *state = pcg(*state);
+    (*state >> 8) as f32 / 16777216.0 * 2.0 - 1.0
+}
+
+fn cell_of(pos: Vec2, settings: &WorldSettings) -> (i32, i32) {
+    let cell = (pos / Vec2::from(settings.size) * GRID_SIZE as f32).floor();
+    (
+        (cell.x as i32).clamp(0, GRID_SIZE as i32 - 1),
+        (cell.y as i32).clamp(0, GRID_SIZE as i32 - 1),
+    )
+}
+
+fn cell_index(pos: Vec2, settings: &WorldSettings) -> usize {
+    let (x, y) = cell_of(pos, settings);
+    (y as u32 * GRID_SIZE + x as u32) as usize
+}
+
+fn glsl_mod(x: f32, y: f32) -> f32 {
+    x - y * (x / y).floor() // rem_euclid rounds differently
+}
+
+fn wrap_delta(mut d: Vec2, settings: &WorldSettings) -> Vec2 {
+    if settings.boundary_x == BOUNDARY_WRAP {
+        d.x -= settings.size[0] * (d.x / settings.size[0] + 0.5).floor();
+    }
+    if settings.boundary_y == BOUNDARY_WRAP {
+        d.y -= settings.size[1] * (d.y / settings.size[1] + 0.5).floor();
+    }
+    d
+}
+
+fn wrap_offset(mut d: Vec2, settings: &WorldSettings) -> Vec2 {
+    if settings.boundary_x == BOUNDARY_WRAP {
+        d.x = glsl_mod(d.x, settings.size[0]);
+    }
+    if settings.boundary_y == BOUNDARY_WRAP {
+        d.y = glsl_mod(d.y, settings.size[1]);
+    }
+    d
+}
+
+/// False when the particle left through an open side.
+fn apply_boundary(
+    pos: &mut f32,
+    vel: &mut f32,
+    size: f32,
+    mode: u32,
+    low_margin: f32,
+    settings: &WorldSettings,
+) -> bool {
+    if mode == BOUNDARY_WRAP {
+        *pos = glsl_mod(*pos, size);
+        return true;
+    }
+    if *pos < 0.0 || *pos > size {
+        if mode == BOUNDARY_OPEN {
+            return false;
+        }
+        *pos = pos.clamp(0.0, size);
+        *vel *= -settings.restitution;
+    }
+    if mode == BOUNDARY_WALL {
+        if *pos <= low_margin {
+            *pos += settings.wall_push;
+        } else if *pos >= size - settings.wall_margin {
+            *pos -= settings.wall_push;
+        }
+    }
+    true
+}
+
+fn collide_terrain(
+    pos: &mut Vec2,
+    vel: &mut Vec2,
+    from: Vec2,
+    terrain: &Terrain,
+    settings: &WorldSettings,
+) {
+    let solid = |at: Vec2| terrain.is_solid(at, settings.size);
+    if !solid(*pos) || solid(from) {
+        return;
+    }
+    if !solid(Vec2::new(pos.x, from.y)) {
+        pos.y = from.y;
+        vel.y *= -settings.restitution;
+    } else if !solid(Vec2::new(from.x, pos.y)) {
+        pos.x = from.x;
+        vel.x *= -settings.restitution;
+    } else {
+        *pos = from;
+        *vel *= -settings.restitution;
+    }
+}
+
+/// Which way the grain is pushed out, from the centre or a rotated box's nearest face, and how
+/// deep it is. None when outside.
+fn hitbox_overlap(hitbox: &Hitbox, pos: Vec2, settings: &WorldSettings) -> Option<(Vec2, f32)> {
+    let size = Vec2::from(hitbox.size);
+    let centre = Vec2::from(hitbox.pos) + size / 2.0;
+    if hitbox.shape == SHAPE_CIRCLE {
+        let local = wrap_delta(pos - centre, settings);
+        let depth = hitbox.radius - local.length();
+        return (depth > 0.0).then_some((local, depth));
+    }
+    if hitbox.shape == SHAPE_ROTATED {
+        let along = Vec2::from_angle(-hitbox.angle).rotate(wrap_delta(pos - centre, settings));
+        let inset = size / 2.0 - along.abs();
+        let side = |v: f32| if v < 0.0 { -1.0 } else { 1.0 };
+        let normal = if inset.x < inset.y {
+            Vec2::new(side(along.x), 0.0)
+        } else {
+            Vec2::new(0.0, side(along.y))
+        };
+        let depth = inset.x.min(inset.y);
+        let local = Vec2::from_angle(hitbox.angle).rotate(normal);
+        return (depth > 0.0).then_some((local, depth));
+    }
+    let local = wrap_offset(pos - Vec2::from(hitbox.pos), settings);
+    let inside = local.x < size.x && local.y < size.y && local.x > 0.0 && local.y > 0.0;
+    let local = local - size / 2.0;
+    inside.then_some((local, (size / 2.0).length() - local.length().abs()))
+}
+
+fn conductivity_of(types: &[MaterialType], id: u32) -> f32 {
+    types.get(id as usize).map_or(0.0, |kind| kind.conductivity)
+}
+
+fn turn_into(m: &mut Material, types: &[MaterialType], id: u32) {
+    let kind = types[id as usize];
+    m.id = id;
+    m.target = m.pos; // the new material is free, nothing pulls it back where it was
+    m.force = 0.0;
+    m.colour = kind.colour;
+    m.mass = kind.mass;
+    m.stable = kind.stable;
+    m.gas = kind.gas;
+    m.tags = kind.tags;
+}
+
+fn simulate(
+    idx: usize,
+    src: &[Material],
+    cells: &[Vec<u32>],
+    hitboxes: &[Hitbox],
+    impulses: &mut [[i32; 2]],
+    contacts: &mut [i32],
+    registry: &MaterialRegistry,
+    settings: &WorldSettings,
+    terrain: &Terrain,
+    mut rng: u32,
+) -> Material {
+    let types = &registry.types[..];
+    let mut m = src[idx];
+    if m.alive == 0 {
+        return m;
+    }
+    let ts = settings.dt * BASE_RATE;
+    let radius = settings.radius;
+    let gravity = settings.gravity * ts;
+    let mut pos = Vec2::from(m.pos);
+    let mut vel = Vec2::from(m.vel);
+    let mut neighbour_mass = 0f32;
+    let mut neighbour_vel = Vec2::ZERO;
+    let mut neighbours = 0f32;
+    let mut touching = 0u32;
+    let mut heat = 0f32;
+    let conductivity = conductivity_of(types, m.id);
+    let (home_x, home_y) = cell_of(pos, settings);
+    for dy in -1..=1 {
+        for dx in -1..=1 {
+            let (mut x, mut y) = (home_x + dx, home_y + dy);
+            if settings.boundary_x == BOUNDARY_WRAP {
+                x = (x + GRID_SIZE as i32) % GRID_SIZE as i32;
+            }
+            if settings.boundary_y == BOUNDARY_WRAP {
+                y = (y + GRID_SIZE as i32) % GRID_SIZE as i32;
+            }
+            if x < 0 || y < 0 || x >= GRID_SIZE as i32 || y >= GRID_SIZE as i32 {
+                continue;
+            }
+            for &i in &cells[(y as u32 * GRID_SIZE + x as u32) as usize] {
+                let other = &src[i as usize];
+                let dir = wrap_delta(pos - Vec2::from(other.pos), settings);
+                let size = dir.length();
+                if size < radius && i as usize != idx {
+                    vel += ((radius - size) * (1.0 / radius)).powf(0.5) * dir * ts;
+                    neighbour_mass += other.mass;
+                    neighbour_vel += Vec2::from(other.vel);
+                    neighbours += 1.0;
+                    touching |= other.tags;
+                    heat += (other.temperature - m.temperature)
+                        * (conductivity + conductivity_of(types, other.id))
+                        * 0.5;
+                }
+            }
+        }
+    }
+
+    if neighbours > 0.0 && (m.id as usize) < types.len() {
+        let friction = (types[m.id as usize].friction * ts).min(1.0);
+        vel = vel * (1.0 - friction) + neighbour_vel / neighbours * friction;
+        // glsl mix
+    }
+
+    for reaction in &registry.reactions {
+        if m.tags & reaction.tag == 0
+            || (reaction.touching != 0 && touching & reaction.touching == 0)
+        {
+            continue;
+        }
+        if random(&mut rng) * 0.5 + 0.5 < reaction.chance * ts {
+            turn_into(&mut m, types, reaction.into);
+            break;
+        }
+    }
+
+    if let Some(kind) = types.get(m.id as usize).copied() {
+        if neighbours > 0.0 {
+            m.temperature += heat / neighbours * ts.min(1.0);
+        }
+        if kind.melt_into != 0 && m.temperature > kind.melt_point {
+            turn_into(&mut m, types, kind.melt_into);
+        } else if kind.freeze_into != 0 && m.temperature < kind.freeze_point {
+            turn_into(&mut m, types, kind.freeze_into);
+        }
+    }
+
+    if m.gas == GAS_NORMAL {
+        vel.y += gravity;
+    } else if m.gas == GAS_ANTIGRAVITY {
+        vel.y -= gravity;
+    } else if m.gas == GAS_BUOYANT && neighbours > 0.0 {
+        let surrounding = neighbour_mass / neighbours;
+        vel.y += gravity * ((m.mass - surrounding) / surrounding.max(0.0001)).clamp(-1.0, 1.0);
+    }
+
+    for (i, (hitbox, impulse)) in hitboxes.iter().zip(impulses.iter_mut()).enumerate() {
+        if hitbox.simulate == 0 || hitbox.deleted != 0 {
+            continue;
+        }
+        if let Some((local, mag)) = hitbox_overlap(hitbox, pos, settings) {
+            let push_back = -local.normalize() * mag / hitbox.mass * ts;
+            let fixed = (push_back * IMPULSE_SCALE + 0.5).floor();
+            impulse[0] = impulse[0].wrapping_add(fixed.x as i32); // atomicAdd wraps too
+            impulse[1] = impulse[1].wrapping_add(fixed.y as i32);
+            let materials = types.len();
+            let material = if (m.id as usize) < materials {
+                m.id as usize
+            } else {
+                0
+            };
+            let slot = (i * materials + material) * CONTACT_FIELDS;
+            contacts[slot] = contacts[slot].wrapping_add(fixed.x as i32);
+            contacts[slot + 1] = contacts[slot + 1].wrapping_add(fixed.y as i32);
+            contacts[slot + 2] += 1;
+            vel += local.normalize() * mag / m.mass * ts;
+        }
+    }
+
+    if m.force > 0.0 {
+        let offset = wrap_delta(Vec2::from(m.target) - pos, settings);
+        if offset.length() > m.stable {
+            m.force = 0.0;
+        } else {
+            vel += offset * m.force * ts;
+            vel *= 0.5f32.powf(ts);
+        }
+    }
+
+    let jitter_x = random(&mut rng);
+    vel += Vec2::new(jitter_x, random(&mut rng)) * settings.jitter * ts;
+    let from = pos;
+    pos += vel / 100.0 * ts;
+    collide_terrain(&mut pos, &mut vel, from, terrain, settings);
+    let size = settings.size;
+    if !apply_boundary(
+        &mut pos.x,
+        &mut vel.x,
+        size[0],
+        settings.boundary_x,
+        settings.wall_margin_left,
+        settings,
+    ) || !apply_boundary(
+        &mut pos.y,
+        &mut vel.y,
+        size[1],
+        settings.boundary_y,
+        settings.wall_margin,
+        settings,
+    ) {
+        m.alive = 0;
+        m.pos = pos.into();
+        m.vel = vel.into();
+        return m;
+    }
+    if vel.length() > settings.max_speed {
+        vel = vel / vel.length() * settings.max_speed;
+    }
+    vel *= settings.damping.powf(ts);
+
+    m.pos = pos.into();
+    m.vel = vel.into();
+    m
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::sand::sand_shader::Reaction;
+    use crate::simulation::sand::SHAPE_BOX;
+
+    fn registry() -> MaterialRegistry {
+        MaterialRegistry {
+            types: vec![MaterialType::default()],
+            ..Default::default()
+        }
+    }
+
+    /// No jitter or gravity unless a test wants it, so one grain moves in a straight line.
+    fn still() -> WorldSettings {
+        WorldSettings {
+            gravity: 0.0,
+            jitter: 0.0,
+            ..Default::default()
+        }
+    }
+
+    fn grain(pos: [f32; 2], vel: [f32; 2]) -> Material {
+        Material {
+            pos,
+            vel,
+            target: pos,
+            ..Default::default()
+        }
+    }
+
+    fn run(
+        world: &mut [Material],
+        hitboxes: &mut [Hitbox],
+        registry: &MaterialRegistry,
+        settings: &WorldSettings,
+        seed: u32,
+        ticks: u32,
+    ) -> Vec<u32> {
+        let mut killed = Vec::new();
+        for tick in 0..ticks {
+            let clock = Clock { seed, tick };
+            let count = world.len();
+            let (gone, _) = super::tick(
+                world,
+                hitboxes,
+                registry,
+                settings,
+                &Terrain::empty(),
+                &clock,
+                count,
+            );
+            killed.extend(gone);
+        }
+        killed
+    }
+
+    #[test]
+    fn gravity_pulls_grains_down() {
+        let settings = WorldSettings {
+            gravity: 0.001,
+            ..still()
+        };
+        let mut world = [grain([0.5, 0.5], [0.0, 0.0])];
+        run(&mut world, &mut [], &registry(), &settings, 1, 1);
+        assert!(world[0].vel[1] > 0.0);
+        assert!(world[0].pos[1] > 0.5);
+        assert_eq!(world[0].pos[0], 0.5);
+    }
+
+    #[test]
+    fn walls_keep_grains_inside() {
+        let mut world = [grain([0.5, 0.999], [0.0, 0.1])];
+        run(&mut world, &mut [], &registry(), &still(), 1, 20);
+        assert_eq!(world[0].alive, 1);
+        assert!(world[0].pos[1] <= 1.0);
+        assert!(world[0].pos[1] >= 1.0 - still().wall_margin - still().wall_push);
+    }
+
+    #[test]
+    fn wrap_brings_grains_round() {
+        let settings = WorldSettings {
+            boundary_x: BOUNDARY_WRAP,
+            ..still()
+        };
+        let mut world = [grain([0.999, 0.5], [0.1, 0.0])];
+        run(&mut world, &mut [], &registry(), &settings, 1, 1);
+        assert_eq!(world[0].alive, 1);
+        assert!(world[0].pos[0] < 0.01, "at {:?}", world[0].pos);
+    }
+
+    #[test]
+    fn open_sides_kill_grains() {
+        let settings = WorldSettings {
+            boundary_y: BOUNDARY_OPEN,
+            ..still()
+        };
+        let mut world = [
+            grain([0.5, 0.9995], [0.0, 0.1]),
+            grain([0.5, 0.5], [0.0, 0.0]),
+        ];
+        let killed = run(&mut world, &mut [], &registry(), &settings, 1, 1);
+        assert_eq!(killed, [0]);
+        assert_eq!(world[0].alive, 0);
+        assert_eq!(world[1].alive, 1);
+    }
+
+    #[test]
+    fn reactions_change_material() {
+        let mut registry = registry();
+        registry.types.push(MaterialType {
+            tags: 1,
+            ..Default::default()
+        });
+        registry.types.push(MaterialType {
+            colour: [0.0, 1.0, 0.0],
+            ..Default::default()
+        });
+        registry.reactions.push(Reaction {
+            tag: 1,
+            touching: 0,
+            into: 2,
+            chance: 2.0, // always, random tops out at 1
+        });
+        let mut world = [Material {
+            id: 1,
+            tags: 1,
+            force: 1.0,
+            stable: 1.0,
+            target: [0.2, 0.2],
+            ..grain([0.5, 0.5], [0.0, 0.0])
+        }];
+        run(&mut world, &mut [], &registry, &still(), 1, 1);
+        assert_eq!(world[0].id, 2);
+        assert_eq!(world[0].colour, [0.0, 1.0, 0.0]);
+        assert_eq!(world[0].tags, 0);
+        assert_eq!(world[0].force, 0.0); // no longer pulled back to where it was pinned
+    }
+
+    #[test]
+    fn grains_push_hitboxes() {
+        let mut hitboxes = [Hitbox {
+            pos: [0.4, 0.4],
+            size: [0.2, 0.2],
+            shape: SHAPE_BOX,
+            ..Default::default()
+        }];
+        let mut world = [grain([0.45, 0.5], [0.0, 0.0])];
+        let (_, contacts) = super::tick(
+            &mut world,
+            &mut hitboxes,
+            &registry(),
+            &still(),
+            &Terrain::empty(),
+            &Clock { seed: 1, tick: 0 },
+            1,
+        );
+        assert_eq!(contacts.len(), 1);
+        assert_eq!(contacts[0].entity, 0);
+        assert!(hitboxes[0].vel[0] > 0.0); // the grain is left of centre
+        assert!(world[0].vel[0] < 0.0);
+    }
+
+    #[test]
+    fn rotated_hitboxes_push_out_the_nearest_face() {
+        let mut hitboxes = [Hitbox {
+            pos: [0.3, 0.45],
+            size: [0.4, 0.1],
+            shape: SHAPE_ROTATED,
+            angle: std::f32::consts::FRAC_PI_2, // now 0.1 wide and 0.4 tall around (0.5, 0.5)
+            ..Default::default()
+        }];
+        let mut world = [grain([0.52, 0.65], [0.0, 0.0])]; // near the tip, but nearest the right side
+        run(&mut world, &mut hitboxes, &registry(), &still(), 1, 1);
+        assert!(world[0].vel[0] > 0.0);
+        assert!(world[0].vel[1].abs() < 1e-6);
+    }
+
+    #[test]
+    fn same_seed_replays_exactly() {
+        let settings = WorldSettings {
+            jitter: 0.001,
+            gravity: 0.0005,
+            ..still()
+        };
+        let block: Vec<Material> = (0..100)
+            .map(|i| {
+                let pos = [0.3 + (i % 10) as f32 * 0.015, 0.3 + (i / 10) as f32 * 0.015];
+                grain(pos, [0.0, 0.0])
+            })
+            .collect();
+        let replay = |seed| {
+            let mut world = block.clone();
+            let mut hitboxes = [Hitbox {
+                pos: [0.3, 0.45],
+                size: [0.15, 0.05],
+                ..Default::default()
+            }];
+            run(&mut world, &mut hitboxes, &registry(), &settings, seed, 30);
+            let grains: Vec<_> = world.iter().map(|m| (m.pos, m.vel, m.id)).collect();
+            (grains, hitboxes[0].vel)
+        };
+        assert_eq!(replay(7), replay(7));
+        assert_ne!(replay(7), replay(8));
+    }
+}
@@ -0,0 +1,74 @@
+use std::fs;
+use std::io::Cursor;
+
+use glam::Vec2;
+
+/// Immovable geometry painted in data/terrain.png, black is solid. The image is stretched over the
+/// whole world whatever its size, one byte per pixel with 255 for solid so it uploads as R8_UNORM.
+#[derive(Clone, Debug)]
+pub struct Terrain {
+    pub width: u32,
+    pub height: u32,
+    pub solid: Vec<u8>,
+}
+
+impl Terrain {
+    /// A missing file is an empty level, anything darker than mid grey is solid and
+    /// transparent pixels never are.
+    pub fn load(path: &str) -> Terrain {
+        let Ok(png_bytes) = fs::read(path) else {
+            return Terrain::empty();
+        };
+        let mut decoder = png::Decoder::new(Cursor::new(png_bytes));
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16); // 8 bits per sample whatever was saved
+        let mut reader = decoder.read_info().unwrap();
+        let mut image_data = vec![0; reader.output_buffer_size()];
+        let frame = reader.next_frame(&mut image_data).unwrap();
+        let samples = frame.color_type.samples();
+        let has_alpha = matches!(
+            frame.color_type,
+            png::ColorType::GrayscaleAlpha | png::ColorType::Rgba
+        );
+
+        let solid = image_data[..frame.buffer_size()]
+            .chunks_exact(samples)
+            .map(|pixel| {
+                let brightness = if samples >= 3 {
+                    (pixel[0] as u32 + pixel[1] as u32 + pixel[2] as u32) / 3
+                } else {
+                    pixel[0] as u32
+                };
+                let opaque = !has_alpha || pixel[samples - 1] >= 128;
+                if opaque && brightness < 128 {
+                    255
+                } else {
+                    0
+                }
+            })
+            .collect();
+        Terrain {
+            width: frame.width,
+            height: frame.height,
+            solid,
+        }
+    }
+
+    /// One empty pixel, images can't be zero sized.
+    pub fn empty() -> Terrain {
+        Terrain {
+            width: 1,
+            height: 1,
+            solid: vec![0],
+        }
+    }
+
+    /// MUST BE KEPT IN SYNC WITH solid() in sand_particle.glsl, outside the image is never solid.
+    pub fn is_solid(&self, pos: Vec2, size: [f32; 2]) -> bool {
+        let px =
+            (pos / Vec2::from(size) * Vec2::new(self.width as f32, self.height as f32)).floor();
+        if px.x < 0.0 || px.y < 0.0 || px.x >= self.width as f32 || px.y >= self.height as f32 {
+            return false;
+        }
+        self.solid[(px.y as u32 * self.width + px.x as u32) as usize] != 0
+    }
+}
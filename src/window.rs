@@ -1,13 +1,18 @@
-use std::fs;
-use std::sync::Arc;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
 use std::time::SystemTime;
 
+use crate::lua_funcs::{Controls, FrameInfo};
 use crate::{deploy_shader, lua_funcs};
 
 use crate::simulation::ecs::{self, Entity};
-use crate::simulation::sand::sand_shader::Hitbox;
+use crate::simulation::materials::MaterialRegistry;
+use crate::simulation::sand::sand_shader::{Clock, Hitbox, WorldSettings};
 use crate::simulation::sand::upload_standard_buffer;
-use crate::simulation::sand::{self, sand_shader::Material, PADDING};
+use crate::simulation::sand::{
+    self, sand_shader::Material, Backend, ParticlePool, HITBOX_PADDING, PADDING,
+};
+use crate::simulation::terrain::Terrain;
 use rlua::Value::Nil;
 use rlua::{Chunk, Lua};
 use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
@@ -29,7 +34,7 @@ use vulkano::sync::future::{FenceSignalFuture, NowFuture};
 use vulkano::sync::{self, FlushError, GpuFuture};
 use vulkano::VulkanLibrary;
 use winit::dpi::PhysicalSize;
-use winit::event::{Event, WindowEvent};
+use winit::event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::Window;
 
@@ -37,6 +42,8 @@ use self::init::fragment_shader::Sprite;
 
 mod fps;
 pub mod init;
+mod reload;
+mod timestep;
 mod utils;
 
 const FPS_DISPLAY: bool = false;
@@ -49,7 +56,7 @@ pub fn make_window(
     device: Arc<Device>,
     compute_queue: Arc<Queue>,
     world: Vec<Padded<Material, PADDING>>,
-    work_groups: [u32; 3],
+    pool: ParticlePool,
     physical_device: Arc<PhysicalDevice>,
     window: Arc<Window>,
     surface: Arc<Surface>,
@@ -57,6 +64,12 @@ pub fn make_window(
     window_size_start: PhysicalSize<u32>,
     init_entities: Vec<Entity>,
     lua_obj: Lua,
+    registry: MaterialRegistry,
+    settings: WorldSettings,
+    terrain: Terrain,
+    max_catch_up: u32,
+    backend: Backend,
+    seed: u32,
 ) {
     // let WindowInitialized {
     //     physical_device,
@@ -79,8 +92,11 @@ pub fn make_window(
     let mut time = 0f64;
     //compute
     let world_buffer_accessible = sand::upload_transfer_source_buffer(world, &memory_allocator);
-    let world_buffer_inaccessible =
-        sand::upload_device_buffer(&memory_allocator, (work_groups[0] * 64) as u64);
+    let mut world_buffer_inaccessible =
+        sand::upload_device_buffer(&memory_allocator, pool.capacity as u64);
+    let mut world_buffer_swap = sand::upload_device_buffer(&memory_allocator, pool.capacity as u64); // every other substep writes here
+    let (mut grid_buffer, mut sorted_buffer) =
+        sand::upload_grid_buffers(&memory_allocator, pool.capacity as u64);
 
     // Create one-time command to copy between the buffers.
     let command_buffer_allocator =
@@ -111,28 +127,61 @@ pub fn make_window(
     let compute_shader_loaded =
         sand::sand_shader::load(device.clone()).expect("Failed to create compute shader.");
 
-    let mut entities = init_entities;
-
-    let mut sprites_collection = entities
+    let mut sprites_collection = init_entities
         .clone()
         .into_iter()
         .map(|e| Padded::<Sprite, 4>(e.sprite))
         .collect();
     let mut sprite_buffer = upload_standard_buffer(sprites_collection, &memory_allocator);
 
-    let mut hitbox_collection = entities
+    let mut hitbox_collection = init_entities
         .clone()
         .into_iter()
-        .map(|e| Padded::<Hitbox, 4>(e.hitbox))
+        .map(|e| Padded::<Hitbox, HITBOX_PADDING>(e.hitbox))
         .collect();
     let mut hitbox_buffer = upload_standard_buffer(hitbox_collection, &memory_allocator);
+    let impulse_buffer = sand::upload_impulse_buffer(&memory_allocator, hitbox_buffer.len());
+    let contacts_buffer = sand::upload_contacts_buffer(
+        &memory_allocator,
+        hitbox_buffer.len(),
+        registry.types.len() as u64,
+    );
+
+    let material_types_buffer = upload_standard_buffer(registry.gpu_types(), &memory_allocator);
+    let reactions_buffer = upload_standard_buffer(registry.reactions.clone(), &memory_allocator);
+    let settings_buffer = sand::upload_uniform_buffer(settings, &memory_allocator);
+    let count_buffer = sand::upload_uniform_buffer(pool.next, &memory_allocator);
+    let mut last_count = pool.next;
+    let clock_buffer = sand::upload_uniform_buffer(Clock { seed, tick: 0 }, &memory_allocator);
+    let mut killed_buffer = sand::upload_killed_buffer(&memory_allocator, pool.capacity);
+    let mut sim_tick = 0u32; // ticks run since startup, only counts ticks that actually simulated
+    let terrain_image = sand::upload_terrain_image(
+        &memory_allocator,
+        &command_buffer_allocator,
+        &compute_queue,
+        &terrain,
+    );
+    let work_groups = [pool.capacity / 64, 1, 1];
+    let controls = Controls::new(settings, registry, pool);
 
-    let deploy_command = Arc::new(deploy_shader::get_deploy_command(
+    let mut deploy_command = Arc::new(deploy_shader::get_deploy_command(
         &compute_shader_loaded,
         &device,
         &compute_queue,
         &world_buffer_inaccessible,
+        &world_buffer_swap,
         &hitbox_buffer,
+        &grid_buffer,
+        &sorted_buffer,
+        &material_types_buffer,
+        &reactions_buffer,
+        &settings_buffer,
+        &count_buffer,
+        &clock_buffer,
+        &killed_buffer,
+        &impulse_buffer,
+        &contacts_buffer,
+        &terrain_image,
         work_groups,
     ));
 
@@ -163,6 +212,9 @@ pub fn make_window(
         compute_queue.clone(),
         &world_buffer_inaccessible,
         &sprite_buffer,
+        &count_buffer,
+        &settings_buffer,
+        &terrain_image.0,
         &command_buffer_allocator,
         &memory_allocator,
         &device,
@@ -187,6 +239,7 @@ pub fn make_window(
     );
 
     let mut next_future: Option<FenceSignalFuture<CommandBufferExecFuture<NowFuture>>> = None;
+    let mut timestep = timestep::FixedTimestep::new(max_catch_up);
 
     // lua
     let mut frame_lua = 0;
@@ -194,15 +247,23 @@ pub fn make_window(
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap()
         .as_millis();
+    let entities = Arc::new(Mutex::new(init_entities)); // shared with the lua bindings
+    let frame_info = FrameInfo::new(time_lua);
+
+    let mut load_errors = BTreeMap::new(); // scripts that didn't load by file, shown until they do
+    let mut shown_error = None;
+    let mut watcher = reload::Watcher::new("./data");
 
     lua_obj.context(|ctx| {
-        lua_funcs::create(ctx, entities.clone(), frame_lua.clone(), time_lua.clone()); // initialise funcs after world init because entities don't exist then.
+        lua_funcs::create(ctx, entities.clone(), frame_info.clone(), controls.clone()); // initialise funcs after world init because entities don't exist then.
         let globals = ctx.globals();
-        let tick_handle = ctx // load the tick method
-            .load(&fs::read_to_string("./data/tick.lua").unwrap()[..])
-            .into_function()
-            .unwrap();
-        globals.set("RS_tick_handle", tick_handle);
+        // load the tick method
+        match lua_funcs::load_script(ctx, "./data/tick.lua") {
+            Ok(tick_handle) => globals.set("RS_tick_handle", tick_handle).unwrap(),
+            Err(error) => {
+                load_errors.insert("tick.lua".to_owned(), lua_funcs::describe(&error));
+            }
+        }
     });
 
     event_loop.run(move |event, _, control_flow| match event {
@@ -229,6 +290,21 @@ pub fn make_window(
         } => {
             recreate_swapchain = true;
         }
+        Event::WindowEvent {
+            event:
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(key),
+                            ..
+                        },
+                    ..
+                },
+            ..
+        } => {
+            handle_key(&mut controls.lock().unwrap(), key);
+        }
         Event::RedrawEventsCleared => {
             // render stuff
             window_size = window.inner_size();
@@ -253,7 +329,10 @@ pub fn make_window(
                     &fs,
                     &world_buffer_inaccessible,
                     &sprite_buffer,
+                    &count_buffer,
+                    &settings_buffer,
                     &texture,
+                    &terrain_image.0,
                     sampler.clone(),
                     init::fragment_shader::PushType {
                         dims: [window_size.width as f32, window_size.height as f32],
@@ -302,7 +381,50 @@ pub fn make_window(
                 fps::do_fps(&mut frames, &mut cur_frame, &mut time);
             }
 
-            for _ in 0..5 {
+            let (ticks, tick_length) = {
+                let mut controls = controls.lock().unwrap();
+                let tick_length = controls.settings.dt as f64 * sand::SUBSTEPS_PER_TICK as f64;
+                if !controls.paused {
+                    (
+                        timestep.ticks(tick_length, controls.time_scale as f64),
+                        tick_length,
+                    )
+                } else {
+                    timestep.skip();
+                    if controls.step_frames > 0 {
+                        controls.step_frames -= 1;
+                        (1, tick_length)
+                    } else {
+                        (0, tick_length)
+                    }
+                }
+            };
+            let mut contacts = Vec::new(); // grains that touched entities over this frame's ticks
+            for _ in 0..ticks {
+                let clock = Clock {
+                    seed,
+                    tick: sim_tick,
+                };
+                sim_tick += 1;
+                *clock_buffer.write().unwrap() = clock; // the last tick was waited on so nothing reads this
+                if backend == Backend::Cpu {
+                    let (killed, touched) = sand::tick_on_cpu(
+                        &memory_allocator,
+                        &command_buffer_allocator,
+                        &compute_queue,
+                        &world_buffer_inaccessible,
+                        &hitbox_buffer,
+                        &controls.lock().unwrap().registry,
+                        &settings_buffer.read().unwrap(),
+                        &terrain,
+                        &clock,
+                        last_count,
+                    );
+                    controls.lock().unwrap().pool.release(&killed);
+                    contacts.extend(touched);
+                    continue;
+                }
+                // each tick is a pair of substeps so the result always lands back in world_buffer_inaccessible
                 next_future = Option::from(sand::tick(
                     //TODO 1 frame of lag is broken due to binding buffer to render.
                     &device.clone(),
@@ -323,23 +445,115 @@ pub fn make_window(
                     //     }
                     // }
                 }
+                // particles that left through an open boundary are already dead on the gpu
+                let killed = sand::drain_killed(&killed_buffer);
+                controls.lock().unwrap().pool.release(&killed);
+                let materials = controls.lock().unwrap().registry.types.len();
+                contacts.extend(sand::drain_contacts(&contacts_buffer, materials));
+            }
+            let mut changed = watcher.changed(); // scripts saved since the last poll, or F5
+            if std::mem::take(&mut controls.lock().unwrap().reload) {
+                for name in reload::RELOADABLE {
+                    if !changed.iter().any(|changed| changed == name) {
+                        changed.push(name.to_owned());
+                    }
+                }
+            }
+            if !changed.is_empty() {
+                lua_obj.context(|ctx| {
+                    reload::reload_scripts(ctx, &controls, &changed, &mut load_errors)
+                });
             }
             // ecs stuff
-            lua_obj.context(|ctx| {
+            let tick_error = lua_obj.context(|ctx| {
                 frame_lua += 1;
                 time_lua = SystemTime::now()
                     .duration_since(SystemTime::UNIX_EPOCH)
                     .unwrap()
                     .as_millis();
                 ecs::regenerate(
-                    &mut entities,
+                    &entities,
                     &mut sprite_buffer,
                     &mut hitbox_buffer,
                     ctx,
+                    &frame_info,
                     frame_lua,
                     time_lua,
-                );
+                    ticks as f64 * tick_length,
+                    &contacts,
+                    &controls,
+                )
+                .err()
             });
+            let error = load_errors
+                .values()
+                .cloned()
+                .chain(tick_error.map(|error| lua_funcs::describe(&error)))
+                .reduce(|errors, error| errors + "\n" + &error);
+            show_script_error(&window, &mut shown_error, error);
+            {
+                let mut controls = controls.lock().unwrap();
+                let grown = controls.pool.grow();
+                if let Some(capacity) = grown {
+                    // compute has finished for this frame so the old buffers can be dropped
+                    world_buffer_inaccessible = sand::grow_world_buffer(
+                        &memory_allocator,
+                        &command_buffer_allocator,
+                        &compute_queue,
+                        &world_buffer_inaccessible,
+                        capacity,
+                    );
+                    world_buffer_swap =
+                        sand::upload_device_buffer(&memory_allocator, capacity as u64);
+                    (grid_buffer, sorted_buffer) =
+                        sand::upload_grid_buffers(&memory_allocator, capacity as u64);
+                    killed_buffer = sand::upload_killed_buffer(&memory_allocator, capacity);
+                    deploy_command = Arc::new(deploy_shader::get_deploy_command(
+                        &compute_shader_loaded,
+                        &device,
+                        &compute_queue,
+                        &world_buffer_inaccessible,
+                        &world_buffer_swap,
+                        &hitbox_buffer,
+                        &grid_buffer,
+                        &sorted_buffer,
+                        &material_types_buffer,
+                        &reactions_buffer,
+                        &settings_buffer,
+                        &count_buffer,
+                        &clock_buffer,
+                        &killed_buffer,
+                        &impulse_buffer,
+                        &contacts_buffer,
+                        &terrain_image,
+                        [capacity / 64, 1, 1],
+                    ));
+                    // this frame's render command buffers still draw the old world, they're rebuilt
+                    // at the start of the next one and the bigger count waits until then
+                    recreate_swapchain = true;
+                }
+                sand::write_particles(
+                    &memory_allocator,
+                    &command_buffer_allocator,
+                    &compute_queue,
+                    &world_buffer_inaccessible,
+                    controls.pool.take_pending(),
+                );
+                let count_changed = grown.is_none() && controls.pool.next != last_count;
+                if count_changed || controls.settings_changed {
+                    // compute has finished for this frame but every image still in flight draws
+                    // with these uniforms, not just the one waited on above
+                    wait_for_renders(&fences);
+                }
+                if count_changed {
+                    *count_buffer.write().unwrap() = controls.pool.next;
+                    last_count = controls.pool.next;
+                }
+                if controls.settings_changed {
+                    *settings_buffer.write().unwrap() = controls.settings;
+                    controls.settings_changed = false;
+                }
+            }
 
             // atlas
             // let mut builder = AutoCommandBufferBuilder::primary(
@@ -397,3 +611,43 @@ pub fn make_window(
         _ => (),
     });
 }
+
+/// Debug keys: space pauses, . steps one tick while paused, - and = halve and double the time scale, 0 resets it,
+/// F5 reloads the scripts.
+fn handle_key(controls: &mut Controls, key: VirtualKeyCode) {
+    match key {
+        VirtualKeyCode::Space => controls.paused = !controls.paused,
+        VirtualKeyCode::Period => {
+            controls.paused = true;
+            controls.step_frames += 1;
+        }
+        VirtualKeyCode::Minus => controls.time_scale /= 2f32,
+        VirtualKeyCode::Equals => controls.time_scale *= 2f32,
+        VirtualKeyCode::Key0 => controls.time_scale = 1f32,
+        VirtualKeyCode::F5 => controls.reload = true,
+        _ => {}
+    }
+}
+
+/// Blocks until every submitted frame has finished on the gpu.
+fn wait_for_renders<F: GpuFuture>(fences: &[Option<Arc<FenceSignalFuture<F>>>]) {
+    for fence in fences.iter().flatten() {
+        fence.wait(None).unwrap();
+    }
+}
+
+/// Prints a script error once and keeps it in the title bar until the scripts run clean again.
+fn show_script_error(window: &Window, shown: &mut Option<String>, error: Option<String>) {
+    if *shown == error {
+        return;
+    }
+    match &error {
+        Some(error) => {
+            eprintln!("{error}");
+            let first_line = error.lines().next().unwrap_or_default();
+            window.set_title(&format!("Powder RS - script error: {first_line}"));
+        }
+        None => window.set_title("Powder RS"),
+    }
+    *shown = error;
+}
@@ -2,6 +2,8 @@ use std::fs::{self, File};
 use std::io::{Cursor, Read};
 use std::sync::Arc;
 
+use crate::simulation::sand::sand_shader::WorldSettings;
+
 use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer};
 use vulkano::command_buffer::allocator::{CommandBufferAllocator, StandardCommandBufferAllocator};
 use vulkano::command_buffer::{
@@ -55,6 +57,9 @@ pub fn initialize_swapchain_screen<T, U>(
     render_queue: Arc<Queue>,
     world_buffer: &Subbuffer<[T]>,
     sprite_buffer: &Subbuffer<[U]>,
+    count_buffer: &Subbuffer<u32>,
+    settings_buffer: &Subbuffer<WorldSettings>,
+    terrain_image: &Arc<ImageView<ImmutableImage>>,
     command_buffer_allocator: &StandardCommandBufferAllocator,
     memory_allocator: &GenericMemoryAllocator<Arc<FreeListAllocator>>,
     device: &Arc<Device>,
@@ -154,7 +159,10 @@ pub fn initialize_swapchain_screen<T, U>(
         push_constants,
         world_buffer,
         sprite_buffer,
+        count_buffer,
+        settings_buffer,
         &texture,
+        terrain_image,
         sampler.clone(),
     );
 
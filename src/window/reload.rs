@@ -0,0 +1,124 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use rlua::{Context, Function, Table};
+
+use crate::lua_funcs::{self, Controls};
+use crate::simulation::sand;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// What F5 reloads, the scripts that can change without rebuilding the world.
+pub const RELOADABLE: [&str; 2] = ["tick.lua", "world_settings.lua"];
+
+/// Polls the modified times of the files in a directory, no subfolders.
+pub struct Watcher {
+    dir: String,
+    seen: HashMap<String, SystemTime>,
+    last_poll: Instant,
+}
+
+impl Watcher {
+    pub fn new(dir: &str) -> Watcher {
+        let mut watcher = Watcher {
+            dir: dir.to_owned(),
+            seen: HashMap::new(),
+            last_poll: Instant::now(),
+        };
+        watcher.scan(); // whatever is there at startup isn't a change
+        watcher
+    }
+
+    /// Names of files written or created since the last poll, empty between polls.
+    pub fn changed(&mut self) -> Vec<String> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return Vec::new();
+        }
+        self.last_poll = Instant::now();
+        self.scan()
+    }
+
+    fn scan(&mut self) -> Vec<String> {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        let mut changed = Vec::new();
+        for entry in entries.flatten() {
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if !metadata.is_file() {
+                continue;
+            }
+            let Ok(modified) = metadata.modified() else {
+                continue;
+            };
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if self.seen.insert(name.clone(), modified) != Some(modified) {
+                changed.push(name);
+            }
+        }
+        changed
+    }
+}
+
+/// Recompiles the changed scripts into the running state so globals carry over, then calls
+/// OnReload(name) for each one that reloaded. A script that fails keeps its old version running and
+/// its error in `errors` until it reloads cleanly, the others still reload.
+pub fn reload_scripts(
+    ctx: Context,
+    controls: &Mutex<Controls>,
+    changed: &[String],
+    errors: &mut BTreeMap<String, String>,
+) {
+    let mut reloaded = Vec::new();
+    for name in changed {
+        let result = match &name[..] {
+            "tick.lua" => reload_tick(ctx),
+            "world_settings.lua" => reload_settings(ctx, controls),
+            _ => {
+                println!("{name} changed, restart to apply it"); // the world is built from these
+                continue;
+            }
+        };
+        match result {
+            Ok(()) => {
+                errors.remove(name);
+                reloaded.push(name);
+            }
+            Err(error) => {
+                errors.insert(name.clone(), lua_funcs::describe(&error));
+            }
+        }
+    }
+    for name in reloaded {
+        // looked up after tick.lua reloads so a new OnReload sees its own reload
+        let on_reload = ctx.globals().get::<_, Option<Function>>("OnReload");
+        let called = match on_reload {
+            Ok(Some(on_reload)) => on_reload.call::<_, ()>(name.as_str()),
+            Ok(None) => Ok(()),
+            Err(error) => Err(error),
+        };
+        if let Err(error) = called {
+            errors.insert(name.clone(), lua_funcs::describe(&error));
+        }
+    }
+}
+
+fn reload_tick(ctx: Context) -> rlua::Result<()> {
+    let tick_handle = lua_funcs::load_script(ctx, "./data/tick.lua")?;
+    ctx.globals().set("RS_tick_handle", tick_handle)
+}
+
+fn reload_settings(ctx: Context, controls: &Mutex<Controls>) -> rlua::Result<()> {
+    let script = lua_funcs::load_script(ctx, "./data/world_settings.lua")?;
+    let data: Table = script.call(())?;
+    let mut settings = controls.lock().unwrap().settings;
+    sand::apply_world_settings(&mut settings, &data)?; // unlocked, the table could call bindings
+    let mut controls = controls.lock().unwrap();
+    controls.settings = settings;
+    controls.settings_changed = true;
+    Ok(())
+}
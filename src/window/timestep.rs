@@ -0,0 +1,39 @@
+use std::time::Instant;
+
+/// Turns wall time into a whole number of fixed length ticks, leftover time carries into the next frame.
+pub struct FixedTimestep {
+    accumulator: f64,
+    last: Instant,
+    pub max_catch_up: u32, // most ticks one frame will run, past this the backlog is dropped
+}
+
+impl FixedTimestep {
+    pub fn new(max_catch_up: u32) -> FixedTimestep {
+        FixedTimestep {
+            accumulator: 0f64,
+            last: Instant::now(),
+            max_catch_up,
+        }
+    }
+
+    pub fn ticks(&mut self, tick_length: f64, time_scale: f64) -> u32 {
+        let now = Instant::now();
+        self.accumulator += now.duration_since(self.last).as_secs_f64() * time_scale;
+        self.last = now;
+
+        let ticks = (self.accumulator / tick_length) as u32;
+        if ticks > self.max_catch_up {
+            // too far behind (breakpoints, window dragging), slow down instead of spiralling
+            self.accumulator = 0f64;
+            return self.max_catch_up;
+        }
+        self.accumulator -= ticks as f64 * tick_length;
+        ticks
+    }
+
+    /// Throws away the time since the last call, used while paused so unpausing doesn't catch up.
+    pub fn skip(&mut self) {
+        self.accumulator = 0f64;
+        self.last = Instant::now();
+    }
+}
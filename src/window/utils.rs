@@ -1,3 +1,4 @@
+use crate::simulation::sand::sand_shader::WorldSettings;
 use crate::window::Arc;
 use vulkano::buffer::{BufferContents, Subbuffer};
 use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
@@ -100,7 +101,10 @@ pub fn get_command_buffers<T, U>(
     push_constants: init::fragment_shader::PushType,
     world_buffer: &Subbuffer<[T]>,
     entity_buffer: &Subbuffer<[U]>,
+    count_buffer: &Subbuffer<u32>,
+    settings_buffer: &Subbuffer<WorldSettings>,
     texture_atlas: &Arc<ImageView<ImmutableImage>>,
+    terrain_image: &Arc<ImageView<ImmutableImage>>,
     sampler: Arc<Sampler>,
 ) -> Vec<Arc<PrimaryAutoCommandBuffer>> {
     let command_buffer_allocator =
@@ -117,8 +121,11 @@ pub fn get_command_buffers<T, U>(
                 push_constants,
                 world_buffer,
                 entity_buffer,
+                count_buffer,
+                settings_buffer,
                 device,
                 texture_atlas,
+                terrain_image,
                 sampler.clone(),
             )
         })
@@ -134,8 +141,11 @@ fn build_render_pass<T, U>(
     push_constants: init::fragment_shader::PushType,
     world_buffer: &Subbuffer<[T]>,
     entity_buffer: &Subbuffer<[U]>,
+    count_buffer: &Subbuffer<u32>,
+    settings_buffer: &Subbuffer<WorldSettings>,
     device: &Arc<Device>,
     texture_atlas: &Arc<ImageView<ImmutableImage>>,
+    terrain_image: &Arc<ImageView<ImmutableImage>>,
     sampler: Arc<Sampler>,
 ) -> Arc<PrimaryAutoCommandBuffer> {
     let mut builder = AutoCommandBufferBuilder::primary(
@@ -157,8 +167,11 @@ fn build_render_pass<T, U>(
         [
             WriteDescriptorSet::buffer(0, world_buffer.clone()),
             WriteDescriptorSet::buffer(1, entity_buffer.clone()),
-            WriteDescriptorSet::image_view_sampler(2, texture_atlas.clone(), sampler),
-        ], // 0-2 is the binding
+            WriteDescriptorSet::image_view_sampler(2, texture_atlas.clone(), sampler.clone()),
+            WriteDescriptorSet::buffer(3, count_buffer.clone()),
+            WriteDescriptorSet::buffer(4, settings_buffer.clone()),
+            WriteDescriptorSet::image_view_sampler(5, terrain_image.clone(), sampler), // nearest like the atlas
+        ], // 0-5 is the binding
     ) {
         Ok(res) => res,
         Err(e) => panic!("Error with {e:?}"),
@@ -241,7 +254,10 @@ pub fn recreate_swapchain<T, U>(
     fs: &Arc<ShaderModule>,
     world_buffer: &Subbuffer<[T]>,
     entity_buffer: &Subbuffer<[U]>,
+    count_buffer: &Subbuffer<u32>,
+    settings_buffer: &Subbuffer<WorldSettings>,
     texture_atlas: &Arc<ImageView<ImmutableImage>>,
+    terrain_image: &Arc<ImageView<ImmutableImage>>,
     sampler: Arc<Sampler>,
     push_constants: init::fragment_shader::PushType,
 ) {
@@ -276,7 +292,10 @@ pub fn recreate_swapchain<T, U>(
         push_constants,
         world_buffer,
         entity_buffer,
+        count_buffer,
+        settings_buffer,
         texture_atlas,
+        terrain_image,
         sampler,
     );
 }
\ No newline at end of file